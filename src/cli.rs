@@ -0,0 +1,352 @@
+use anyhow::{anyhow, Result};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI8, Ordering};
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: netmgr [OPTIONS] <COMMAND>
+
+Commands:
+  plan [--out <file>]           Show the changes needed to match the config
+  apply [<plan file>] [--yes]   Apply the config, or a plan saved with `plan --out`
+  validate                      Check the config for errors
+  export [--format <format>] [--out <file>]
+                                Print the records generated from the config
+                                (text, json, bind, hosts, dnsmasq, reverse)
+  import --zone <domain> [--out <file>]
+                                Draft a config from the records in Cloudflare
+  diff                          Show every difference between Cloudflare and the config
+  check                         Exit with 2 when Cloudflare has drifted from the config
+  serve [--listen <addr>]       Answer DNS queries for the private records
+                                [default: 0.0.0.0:53]
+  daemon [--interval <seconds>] [--metrics <addr>]
+                                Keep Cloudflare in sync with the config, every
+                                300 seconds by default, optionally serving
+                                Prometheus metrics and health checks
+  ipam list                     List the address of every server
+
+Options:
+  -c, --config <path>  Path to the config file, or a directory of them
+                       [default: config.yaml]
+  -z, --zone <name>    Only operate on the zone with this domain
+  --public-only        Only publish the public records to Cloudflare,
+                       overriding `backends.cloudflare`
+  -v, --verbose        Print more output, may be repeated
+  -q, --quiet          Only print errors and the plan itself
+  -h, --help           Print this help";
+
+/// Prints to stderr when running with `--verbose`.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::cli::verbosity() > 0 {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+static VERBOSITY: AtomicI8 = AtomicI8::new(0);
+
+/// The shortest interval `netmgr daemon` accepts, so it cannot hammer Cloudflare.
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The verbosity selected on the command line, negative when `--quiet` is given.
+pub fn verbosity() -> i8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Text,
+    Json,
+    Bind,
+    Hosts,
+    Dnsmasq,
+    Reverse,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ExportFormat::Text),
+            "json" => Ok(ExportFormat::Json),
+            "bind" => Ok(ExportFormat::Bind),
+            "hosts" => Ok(ExportFormat::Hosts),
+            "dnsmasq" => Ok(ExportFormat::Dnsmasq),
+            "reverse" => Ok(ExportFormat::Reverse),
+            other => Err(anyhow!("Unknown export format {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Plan {
+        out: Option<PathBuf>,
+    },
+    Apply {
+        plan: Option<PathBuf>,
+        yes: bool,
+    },
+    Validate,
+    Export {
+        format: ExportFormat,
+        out: Option<PathBuf>,
+    },
+    Import {
+        out: Option<PathBuf>,
+    },
+    Diff,
+    Check,
+    Serve {
+        listen: SocketAddr,
+    },
+    Daemon {
+        interval: Duration,
+        metrics: Option<SocketAddr>,
+    },
+    IpamList,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub config: PathBuf,
+    pub zone: Option<String>,
+    pub public_only: bool,
+    pub verbosity: i8,
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn parse() -> Result<Cli> {
+        let cli = Cli::parse_from(std::env::args().skip(1))?;
+        VERBOSITY.store(cli.verbosity, Ordering::Relaxed);
+        Ok(cli)
+    }
+
+    pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> Result<Cli> {
+        let mut args = args.into_iter();
+        let mut config = PathBuf::from("config.yaml");
+        let mut zone = None;
+        let mut verbosity = 0;
+        let mut command = None;
+        let mut out = None;
+        let mut yes = false;
+        let mut format = ExportFormat::Text;
+        let mut positional = Vec::new();
+        let mut help = false;
+        let mut public_only = false;
+        let mut listen = SocketAddr::from(([0, 0, 0, 0], 53));
+        let mut interval = Duration::from_secs(300);
+        let mut metrics = None;
+        // The options only some commands take, to refuse them on the others.
+        let mut specific = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow!("{} requires a value\n\n{}", flag, USAGE))
+            };
+            if matches!(
+                arg.as_str(),
+                "--out" | "--yes" | "-y" | "--format" | "--listen" | "--metrics" | "--interval"
+            ) {
+                specific.push(arg.clone());
+            }
+            match arg.as_str() {
+                "-c" | "--config" => config = PathBuf::from(value(&arg)?),
+                "-z" | "--zone" => zone = Some(value(&arg)?),
+                "-v" | "--verbose" => verbosity += 1,
+                "-vv" => verbosity += 2,
+                "-q" | "--quiet" => verbosity = -1,
+                "-h" | "--help" => help = true,
+                "--out" => out = Some(PathBuf::from(value(&arg)?)),
+                "--yes" | "-y" => yes = true,
+                "--format" => format = value(&arg)?.parse()?,
+                "--public-only" => public_only = true,
+                "--listen" => listen = value(&arg)?.parse()?,
+                "--metrics" => metrics = Some(value(&arg)?.parse()?),
+                "--interval" => {
+                    interval = Duration::from_secs(value(&arg)?.parse()?);
+                    if interval < MIN_INTERVAL {
+                        return Err(anyhow!(
+                            "--interval must be at least {} seconds",
+                            MIN_INTERVAL.as_secs()
+                        ));
+                    }
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(anyhow!("Unknown option {}\n\n{}", flag, USAGE))
+                }
+                _ if command.is_none() => command = Some(arg),
+                _ => positional.push(arg),
+            }
+        }
+
+        if help {
+            command = Some("help".to_string());
+            positional.clear();
+            specific.clear();
+        }
+        let name = command.clone();
+        let command = match command.as_deref() {
+            Some("plan") => Command::Plan { out },
+            Some("apply") => Command::Apply {
+                plan: positional.pop().map(PathBuf::from),
+                yes,
+            },
+            Some("validate") => Command::Validate,
+            Some("export") => Command::Export { format, out },
+            Some("import") => Command::Import { out },
+            Some("diff") => Command::Diff,
+            Some("check") => Command::Check,
+            Some("serve") => Command::Serve { listen },
+            Some("daemon") => Command::Daemon { interval, metrics },
+            Some("ipam") => match positional.first().map(String::as_str) {
+                Some("list") => {
+                    positional.remove(0);
+                    Command::IpamList
+                }
+                _ => return Err(anyhow!("Usage: netmgr ipam list")),
+            },
+            Some("help") => Command::Help,
+            Some(other) => return Err(anyhow!("Unknown command {}\n\n{}", other, USAGE)),
+            None => return Err(anyhow!(USAGE)),
+        };
+        if !positional.is_empty() {
+            return Err(anyhow!(
+                "Unexpected argument {}\n\n{}",
+                positional[0],
+                USAGE
+            ));
+        }
+        let takes: &[&str] = match command {
+            Command::Plan { .. } | Command::Import { .. } => &["--out"],
+            Command::Apply { .. } => &["--yes", "-y"],
+            Command::Export { .. } => &["--format", "--out"],
+            Command::Serve { .. } => &["--listen"],
+            Command::Daemon { .. } => &["--interval", "--metrics"],
+            _ => &[],
+        };
+        if let Some(flag) = specific.iter().find(|f| !takes.contains(&f.as_str())) {
+            return Err(anyhow!(
+                "{} does not apply to {}\n\n{}",
+                flag,
+                name.unwrap_or_default(),
+                USAGE
+            ));
+        }
+        Ok(Cli {
+            config,
+            zone,
+            public_only,
+            verbosity,
+            command,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli> {
+        Cli::parse_from(args.split_whitespace().map(str::to_string))
+    }
+
+    fn command(args: &str) -> Command {
+        parse(args).unwrap().command
+    }
+
+    #[test]
+    fn parses_every_command() {
+        assert_eq!(command("plan"), Command::Plan { out: None });
+        assert_eq!(
+            command("plan --out plan.json"),
+            Command::Plan {
+                out: Some(PathBuf::from("plan.json"))
+            }
+        );
+        assert_eq!(
+            command("apply"),
+            Command::Apply {
+                plan: None,
+                yes: false
+            }
+        );
+        assert_eq!(
+            command("apply plan.json -y"),
+            Command::Apply {
+                plan: Some(PathBuf::from("plan.json")),
+                yes: true
+            }
+        );
+        assert_eq!(command("validate"), Command::Validate);
+        assert_eq!(
+            command("export --format bind --out zone"),
+            Command::Export {
+                format: ExportFormat::Bind,
+                out: Some(PathBuf::from("zone"))
+            }
+        );
+        assert_eq!(
+            command("import --zone example.com"),
+            Command::Import { out: None }
+        );
+        assert_eq!(command("diff"), Command::Diff);
+        assert_eq!(command("check"), Command::Check);
+        assert_eq!(
+            command("serve --listen 127.0.0.1:5353"),
+            Command::Serve {
+                listen: "127.0.0.1:5353".parse().unwrap()
+            }
+        );
+        assert_eq!(
+            command("daemon --interval 60 --metrics 127.0.0.1:9100"),
+            Command::Daemon {
+                interval: Duration::from_secs(60),
+                metrics: Some("127.0.0.1:9100".parse().unwrap())
+            }
+        );
+        assert_eq!(command("ipam list"), Command::IpamList);
+        assert_eq!(command("plan --help"), Command::Help);
+        assert_eq!(command("serve --listen 0.0.0.0:53 -h"), Command::Help);
+    }
+
+    #[test]
+    fn parses_global_options() {
+        let cli = parse("-c conf --zone example.com --public-only -vv diff -v").unwrap();
+        assert_eq!(cli.config, PathBuf::from("conf"));
+        assert_eq!(cli.zone.as_deref(), Some("example.com"));
+        assert!(cli.public_only);
+        assert_eq!(cli.verbosity, 3);
+        assert_eq!(parse("plan").unwrap().config, PathBuf::from("config.yaml"));
+        assert_eq!(parse("-q plan").unwrap().verbosity, -1);
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for args in [
+            "",
+            "plan --bogus",
+            "plan --out",
+            "export --format svg",
+            "serve --listen nowhere",
+            "frobnicate",
+            "ipam",
+            "ipam show",
+            "plan extra",
+            "validate --format bind",
+            "plan --yes",
+            "export --listen 127.0.0.1:53",
+            "apply --out plan.json",
+            "daemon --out file",
+            "daemon --interval 0",
+            "daemon --interval 9",
+        ] {
+            assert!(parse(args).is_err(), "{}", args);
+        }
+    }
+}
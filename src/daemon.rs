@@ -0,0 +1,138 @@
+//! Keeps Cloudflare in sync with the config from a long running process.
+use crate::metrics::Metrics;
+use crate::model;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the config files are checked for changes.
+const POLL: Duration = Duration::from_secs(1);
+/// The longest wait between attempts while syncing keeps failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Runs `sync` every `interval`, and right away when the config at `config`
+/// changes. While it keeps failing the wait doubles after every attempt.
+pub fn run(
+    config: &Path,
+    interval: Duration,
+    metrics: &Metrics,
+    mut sync: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let mut watch = Watch::new(config);
+    let mut failures = 0;
+    loop {
+        let result = sync();
+        metrics.synced(result.is_ok());
+        match result {
+            Ok(()) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                eprintln!("Sync failed: {}", e);
+            }
+        }
+        let wait = backoff(interval, failures);
+        if failures > 0 {
+            eprintln!("Retrying in {}s", wait.as_secs());
+        }
+        let next = Instant::now() + wait;
+        loop {
+            let left = next.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(POLL.min(left));
+            if watch.changed() {
+                verbose!("{} changed", config.display());
+                break;
+            }
+        }
+    }
+}
+
+/// The wait before the next sync after `failures` failed ones in a row.
+pub fn backoff(interval: Duration, failures: u32) -> Duration {
+    match failures {
+        0 => interval,
+        n => interval
+            .saturating_mul(2u32.saturating_pow(n - 1))
+            .min(MAX_BACKOFF.max(interval)),
+    }
+}
+
+/// The modification times of the config files, to notice edits.
+struct Watch {
+    config: PathBuf,
+    seen: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Watch {
+    fn new(config: &Path) -> Watch {
+        let mut watch = Watch {
+            config: config.to_path_buf(),
+            seen: BTreeMap::new(),
+        };
+        watch.seen = watch.snapshot();
+        watch
+    }
+
+    fn snapshot(&self) -> BTreeMap<PathBuf, Option<SystemTime>> {
+        model::config_files(&self.config)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|file| {
+                let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+                (file, modified)
+            })
+            .collect()
+    }
+
+    /// Whether a config file was added, removed or modified since the last call.
+    fn changed(&mut self) -> bool {
+        let now = self.snapshot();
+        if now == self.seen {
+            return false;
+        }
+        self.seen = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_back_off_exponentially() {
+        let minute = Duration::from_secs(60);
+        let waits: Vec<u64> = (0..5)
+            .map(|failures| backoff(minute, failures).as_secs())
+            .collect();
+        assert_eq!(waits, vec![60, 60, 120, 240, 480]);
+        assert_eq!(backoff(minute, 40), MAX_BACKOFF);
+        assert_eq!(backoff(MAX_BACKOFF * 2, 3), MAX_BACKOFF * 2);
+    }
+
+    #[test]
+    fn watch_notices_changed_files() {
+        let dir = std::env::temp_dir().join("netmgr-watch");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("prod.yaml"), "").unwrap();
+        let mut watch = Watch::new(&dir);
+        assert!(!watch.changed());
+
+        fs::write(dir.join("lab.yaml"), "").unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.join("prod.yaml"))
+            .unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(watch.changed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+//! The failures callers may want to tell apart, carried inside `anyhow::Error`.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The API token is missing, invalid or lacks a permission.
+    Auth(String),
+    /// The account has no zone for the domain.
+    MissingZone(String),
+    /// The config has problems, or Cloudflare refused a request as invalid.
+    Validation(String),
+    /// Rate limits, server errors and network failures, which may go away when retried.
+    Transient(String),
+}
+
+impl Error {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Transient(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingZone(domain) => {
+                write!(f, "Unable to find the zone {} in your account", domain)
+            }
+            Error::Auth(message) | Error::Validation(message) | Error::Transient(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,164 @@
+use crate::model::{Record, RecordSettings, Soa};
+use std::fmt::Write;
+
+pub const REFRESH: u32 = 3600;
+pub const RETRY: u32 = 600;
+pub const EXPIRE: u32 = 604800;
+
+/// Renders `records` as an RFC 1035 master file for `origin`. Records without
+/// a TTL of their own get the one of the SOA.
+pub fn zone_file(
+    origin: &str,
+    soa: &Soa,
+    serial: u32,
+    records: &[(Record, RecordSettings)],
+) -> String {
+    let origin = origin.trim_end_matches('.');
+    let nameservers = soa.nameservers(origin);
+    let hostmaster = soa.hostmaster(origin);
+
+    let mut out = String::new();
+    writeln!(out, "$ORIGIN {}.", origin).unwrap();
+    writeln!(out, "$TTL {}", soa.ttl).unwrap();
+    writeln!(
+        out,
+        "@ IN SOA {} {} (\n    {} ; serial\n    {} ; refresh\n    {} ; retry\n    {} ; expire\n    {} ; minimum\n)",
+        absolute(&nameservers[0]),
+        absolute(&hostmaster),
+        serial,
+        REFRESH,
+        RETRY,
+        EXPIRE,
+        soa.ttl
+    )
+    .unwrap();
+    for ns in &nameservers {
+        writeln!(out, "@ IN NS {}", absolute(ns)).unwrap();
+    }
+    out.push('\n');
+
+    let rows: Vec<(String, u32, &str, String)> = records
+        .iter()
+        .map(|(r, settings)| {
+            let ttl = settings.ttl.unwrap_or(soa.ttl);
+            (relative(&r.name(), origin), ttl, r.kind(), rdata(r))
+        })
+        .collect();
+    let width = rows.iter().map(|(name, ..)| name.len()).max().unwrap_or(0);
+    for (name, ttl, kind, data) in rows {
+        writeln!(
+            out,
+            "{:width$} {} IN {:5} {}",
+            name,
+            ttl,
+            kind,
+            data,
+            width = width
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+fn relative(name: &str, origin: &str) -> String {
+    if name == origin {
+        "@".to_string()
+    } else {
+        match name.strip_suffix(&format!(".{}", origin)) {
+            Some(label) => label.to_string(),
+            None => absolute(name),
+        }
+    }
+}
+
+fn rdata(record: &Record) -> String {
+    match record {
+        Record::A(_, ip) | Record::Aaaa(_, ip) => ip.clone(),
+        Record::Cname(_, target) | Record::Ptr(_, target) => absolute(target),
+        Record::Txt(_, text) => quote(text),
+        Record::Mx(_, priority, exchange) => format!("{} {}", priority, absolute(exchange)),
+        Record::Srv(_, priority, weight, port, target) => {
+            format!("{} {} {} {}", priority, weight, port, absolute(target))
+        }
+        Record::Caa(_, flags, tag, value) => format!("{} {} {}", flags, tag, quote(value)),
+    }
+}
+
+/// Quotes TXT data, splitting it into strings of at most 255 characters.
+fn quote(text: &str) -> String {
+    let escaped: Vec<String> = text
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{}", c),
+            c => c.to_string(),
+        })
+        .collect();
+    escaped
+        .chunks(255)
+        .map(|chunk| format!("\"{}\"", chunk.concat()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_a_master_file() {
+        let records = vec![
+            Record::Cname(
+                "nas.office.example.com".to_string(),
+                "gw.office.example.com".to_string(),
+            ),
+            Record::A(
+                "gw.office.int.example.com".to_string(),
+                "10.0.0.1".to_string(),
+            ),
+            Record::Txt("example.com".to_string(), "v=spf1 -all".to_string()),
+        ];
+        let mut records: Vec<(Record, RecordSettings)> = records
+            .into_iter()
+            .map(|r| (r, RecordSettings::default()))
+            .collect();
+        records[1].1 = RecordSettings::new(Some(300), None);
+        let soa = Soa {
+            nameservers: vec!["ns1.example.com".to_string(), "ns2.example.com".to_string()],
+            hostmaster: Some("admin@example.com".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            zone_file("example.com", &soa, 42, &records),
+            "$ORIGIN example.com.
+$TTL 3600
+@ IN SOA ns1.example.com. admin.example.com. (
+    42 ; serial
+    3600 ; refresh
+    600 ; retry
+    604800 ; expire
+    3600 ; minimum
+)
+@ IN NS ns1.example.com.
+@ IN NS ns2.example.com.
+
+nas.office    3600 IN CNAME gw.office.example.com.
+gw.office.int 300 IN A     10.0.0.1
+@             3600 IN TXT   \"v=spf1 -all\"
+"
+        );
+    }
+
+    #[test]
+    fn long_txt_records_are_split() {
+        let quoted = quote(&"a".repeat(300));
+        assert_eq!(
+            quoted,
+            format!("\"{}\" \"{}\"", "a".repeat(255), "a".repeat(45))
+        );
+    }
+}
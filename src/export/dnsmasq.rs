@@ -0,0 +1,105 @@
+use crate::model::Zone;
+use std::fmt::Write;
+
+/// Renders the private zone as `/etc/hosts` lines, one per address.
+pub fn hosts(zone: &Zone) -> String {
+    let mut out = String::new();
+    for network in &zone.networks {
+        let domain = zone.private_domain(network);
+        writeln!(out, "# {}", domain).unwrap();
+        for server in &network.servers {
+            let mut names = vec![format!("{}.{}", server.name, domain)];
+            names.extend(
+                server
+                    .alias
+                    .iter()
+                    .map(|a| format!("{}.{}", a.name, domain)),
+            );
+            if server.name == network.root {
+                names.push(domain.clone());
+            }
+            for ip in server.private_ip.iter().chain(&server.private_ipv6) {
+                writeln!(out, "{} {}", ip, names.join(" ")).unwrap();
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Renders the private zone as dnsmasq `host-record=` and `cname=` directives.
+/// dnsmasq only follows a `cname=` to names it knows from a `host-record=`.
+pub fn dnsmasq(zone: &Zone) -> String {
+    let mut out = String::new();
+    for network in &zone.networks {
+        let domain = zone.private_domain(network);
+        writeln!(out, "# {}", domain).unwrap();
+        for server in &network.servers {
+            let name = format!("{}.{}", server.name, domain);
+            let (v4, v6) = (&server.private_ip, &server.private_ipv6);
+            for i in 0..v4.len().max(v6.len()) {
+                let mut fields = vec![name.as_str()];
+                fields.extend(v4.get(i).map(String::as_str));
+                fields.extend(v6.get(i).map(String::as_str));
+                writeln!(out, "host-record={}", fields.join(",")).unwrap();
+            }
+            for alias in &server.alias {
+                writeln!(out, "cname={}.{},{}", alias.name, domain, name).unwrap();
+            }
+        }
+        writeln!(out, "cname={},{}.{}", domain, network.root, domain).unwrap();
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00::1
+      - name: nas
+        private_ip: [10.0.0.2, 10.0.0.3]
+        alias: [files]
+";
+
+    #[test]
+    fn renders_hosts_lines() {
+        let zone: Zone = serde_yaml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            hosts(&zone),
+            "# office.int.example.com
+10.0.0.1 gw.office.int.example.com office.int.example.com
+fd00::1 gw.office.int.example.com office.int.example.com
+10.0.0.2 nas.office.int.example.com files.office.int.example.com
+10.0.0.3 nas.office.int.example.com files.office.int.example.com
+
+"
+        );
+    }
+
+    #[test]
+    fn renders_dnsmasq_directives() {
+        let zone: Zone = serde_yaml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            dnsmasq(&zone),
+            "# office.int.example.com
+host-record=gw.office.int.example.com,10.0.0.1,fd00::1
+host-record=nas.office.int.example.com,10.0.0.2
+host-record=nas.office.int.example.com,10.0.0.3
+cname=files.office.int.example.com,nas.office.int.example.com
+cname=office.int.example.com,gw.office.int.example.com
+
+"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+mod bind;
+mod dnsmasq;
+
+use crate::cli::ExportFormat;
+use crate::model::{RecordSettings, Soa, Zone};
+use crate::reverse;
+use anyhow::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use bind::{zone_file, EXPIRE, REFRESH, RETRY};
+
+/// Renders the records generated from `zone` for the export backend in the
+/// given format. Hosts files and dnsmasq only ever hold the private records.
+pub fn render(zone: &Zone, format: &ExportFormat) -> Result<String> {
+    let records = zone.records(zone.backends.export);
+    Ok(match format {
+        ExportFormat::Text => records
+            .iter()
+            .map(|r| format!("{} {} {}\n", r.name(), r.kind(), r.value()))
+            .collect(),
+        ExportFormat::Json => serde_json::to_string_pretty(&records)? + "\n",
+        ExportFormat::Bind => zone_file(
+            &zone.domain,
+            &zone.soa,
+            serial(zone),
+            &zone.records_with_settings(zone.backends.export),
+        ),
+        ExportFormat::Hosts => dnsmasq::hosts(zone),
+        ExportFormat::Dnsmasq => dnsmasq::dnsmasq(zone),
+        ExportFormat::Reverse => reverse_zone_files(zone)
+            .into_iter()
+            .map(|(_, file)| file)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
+
+/// Renders every reverse zone of `zone` as a zone file, keyed by its origin.
+/// The nameservers and hostmaster default to names in the forward zone.
+pub fn reverse_zone_files(zone: &Zone) -> Vec<(String, String)> {
+    let serial = serial(zone);
+    let soa = Soa {
+        nameservers: zone.soa.nameservers(&zone.domain),
+        hostmaster: Some(zone.soa.hostmaster(&zone.domain)),
+        ..zone.soa.clone()
+    };
+    reverse::reverse_zones(zone)
+        .into_iter()
+        .map(|r| {
+            let settings = RecordSettings::new(zone.ttl, None);
+            let records: Vec<_> = r.records.into_iter().map(|r| (r, settings)).collect();
+            let file = zone_file(&r.origin, &soa, serial, &records);
+            (r.origin, file)
+        })
+        .collect()
+}
+
+/// The configured SOA serial, or the current unix time when there is none.
+pub fn serial(zone: &Zone) -> u32 {
+    zone.soa.serial.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |d| d.as_secs() as u32)
+    })
+}
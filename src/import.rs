@@ -0,0 +1,330 @@
+//! Drafting a config from the records already live in a zone.
+use crate::model::{
+    Alias, Backends, CustomRecord, Network, Record, RecordTypeFilter, Server, Soa, Zone,
+};
+use crate::plan::Diff;
+use crate::provider::LiveState;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+/// A network while its records are being collected.
+#[derive(Default)]
+struct Draft {
+    root: Option<String>,
+    servers: BTreeMap<String, Server>,
+    records: Vec<CustomRecord>,
+}
+
+/// Drafts the config of `domain` from its live records. Addresses named
+/// `<server>.<network>.<private_prefix>.<domain>` become servers, the CNAMEs
+/// next to them roots and aliases, and TXT, MX, SRV and CAA records the
+/// custom records of the level they are under. Anything else is left out,
+/// see `unmapped`.
+pub fn draft(domain: &str, live: &LiveState) -> Result<Zone> {
+    let private_prefix = private_prefix(domain, &live.records).ok_or_else(|| {
+        anyhow!(
+            "Found no addresses named <server>.<network>.<private_prefix>.{} to import",
+            domain
+        )
+    })?;
+    let private_origin = format!("{}.{}", private_prefix, domain);
+    let mut drafts: BTreeMap<String, Draft> = BTreeMap::new();
+
+    // Servers first, so the other records can be matched against them.
+    for record in &live.records {
+        let name = record.name();
+        let (server, network) = match relative(&name, &private_origin).as_deref() {
+            Some([server, network]) => (server.to_string(), network.to_string()),
+            _ => continue,
+        };
+        let server = drafts
+            .entry(network)
+            .or_default()
+            .servers
+            .entry(server.clone())
+            .or_insert_with(|| new_server(server));
+        match record {
+            Record::A(_, ip) => server.private_ip.push(ip.clone()),
+            Record::Aaaa(_, ip) => server.private_ipv6.push(ip.clone()),
+            _ => {}
+        }
+    }
+    drafts.retain(|_, draft| {
+        draft
+            .servers
+            .retain(|_, s| !s.private_ip.is_empty() || !s.private_ipv6.is_empty());
+        !draft.servers.is_empty()
+    });
+
+    let mut zone_records = Vec::new();
+    for record in &live.records {
+        let name = record.name();
+        let labels = match relative(&name, &private_origin) {
+            Some(labels) => labels,
+            None => {
+                zone_records.extend(CustomRecord::from_record(record, domain));
+                continue;
+            }
+        };
+        let network = match labels.last().and_then(|n| drafts.get_key_value(*n)) {
+            Some((network, _)) => network.clone(),
+            None => continue,
+        };
+        let base = format!("{}.{}", network, private_origin);
+        let draft = drafts.get_mut(&network).unwrap();
+        if let Record::Cname(_, target) = record {
+            let target = match relative(target, &private_origin).as_deref() {
+                Some([server, n]) if *n == network && draft.servers.contains_key(*server) => {
+                    server.to_string()
+                }
+                _ => continue,
+            };
+            match labels[..] {
+                [_] => draft.root = Some(target),
+                [alias, _] if !draft.servers.contains_key(alias) => {
+                    draft.servers.get_mut(&target).unwrap().alias.push(Alias {
+                        name: alias.to_string(),
+                        ttl: None,
+                        proxied: None,
+                    })
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let server = match labels[..] {
+            [.., server, _] => draft.servers.get_mut(server),
+            _ => None,
+        };
+        match server {
+            Some(server) => {
+                let server_name = format!("{}.{}", server.name, base);
+                server
+                    .records
+                    .extend(CustomRecord::from_record(record, &server_name));
+            }
+            None => draft
+                .records
+                .extend(CustomRecord::from_record(record, &base)),
+        }
+    }
+
+    let networks: Vec<Network> = drafts
+        .into_iter()
+        .map(|(name, draft)| {
+            let mut servers: Vec<Server> = draft.servers.into_values().collect();
+            let root = draft.root.unwrap_or_else(|| servers[0].name.clone());
+            let public = |host: &str| format!("{}.{}.{}", host, name, domain);
+            let network_proxied = proxied(live, &format!("{}.{}", name, domain), false);
+            let parent = network_proxied.unwrap_or(false);
+            for server in &mut servers {
+                if server.name != root {
+                    server.proxied = proxied(live, &public(&server.name), parent);
+                }
+                let parent = server.proxied.unwrap_or(parent);
+                for alias in &mut server.alias {
+                    alias.proxied = proxied(live, &public(&alias.name), parent);
+                }
+            }
+            Network {
+                name,
+                root,
+                subnet: Vec::new(),
+                servers,
+                records: draft.records,
+                ttl: None,
+                proxied: network_proxied,
+            }
+        })
+        .collect();
+
+    let mut backends = Backends::default();
+    let public = networks
+        .iter()
+        .any(|n| has_cname(live, &format!("{}.{}", n.name, domain)));
+    if !public {
+        backends.cloudflare = RecordTypeFilter::Private;
+    }
+    Ok(Zone {
+        domain: domain.to_string(),
+        private_prefix,
+        networks,
+        soa: Soa::default(),
+        backends,
+        records: zone_records,
+        ttl: common_ttl(live),
+        proxied: None,
+    })
+}
+
+/// The differences between the live records and those of the draft: records
+/// the draft could not map and records it would publish differently.
+pub fn unmapped(zone: &Zone, live: &LiveState) -> Diff {
+    Diff::new(
+        live.record_sets(),
+        zone.record_sets(zone.backends.cloudflare),
+    )
+}
+
+/// The most common label in front of the domain among addresses three labels below it.
+fn private_prefix(domain: &str, records: &[Record]) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let names: Vec<String> = records
+        .iter()
+        .filter(|r| matches!(r, Record::A(..) | Record::Aaaa(..)))
+        .map(Record::name)
+        .collect();
+    for name in &names {
+        if let Some([_, _, prefix]) = relative(name, domain).as_deref() {
+            *counts.entry(prefix).or_default() += 1;
+        }
+    }
+    // Reversed so ties go to the first prefix in alphabetical order.
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(prefix, _)| prefix.to_string())
+}
+
+/// The labels of `name` in front of `origin`, or `None` if it is not below it.
+fn relative<'a>(name: &'a str, origin: &str) -> Option<Vec<&'a str>> {
+    name.strip_suffix(origin)?
+        .strip_suffix('.')
+        .map(|rest| rest.split('.').collect())
+}
+
+fn new_server(name: String) -> Server {
+    Server {
+        name,
+        private_ip: Vec::new(),
+        private_ipv6: Vec::new(),
+        alias: Vec::new(),
+        records: Vec::new(),
+        ttl: None,
+        proxied: None,
+    }
+}
+
+fn has_cname(live: &LiveState, name: &str) -> bool {
+    live.records
+        .iter()
+        .any(|r| matches!(r, Record::Cname(..)) && r.name() == name)
+}
+
+/// Whether the CNAME at `name` is proxied, when that differs from what it
+/// inherits from its `parent`.
+fn proxied(live: &LiveState, name: &str, parent: bool) -> Option<bool> {
+    let record = live
+        .records
+        .iter()
+        .find(|r| matches!(r, Record::Cname(..)) && r.name() == name)?;
+    let proxied = live.settings[record].proxied == Some(true);
+    (proxied != parent).then_some(proxied)
+}
+
+/// The TTL most of the live records have, if that is not the automatic one.
+fn common_ttl(live: &LiveState) -> Option<u32> {
+    let mut counts: BTreeMap<Option<u32>, usize> = BTreeMap::new();
+    for settings in live.settings.values() {
+        *counts.entry(settings.ttl).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .and_then(|(ttl, _)| ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{RecordSet, RecordSettings};
+    use crate::provider::ZoneRecord;
+
+    fn live(records: Vec<(Record, RecordSettings)>) -> LiveState {
+        LiveState::new(
+            records
+                .into_iter()
+                .enumerate()
+                .map(|(i, (record, settings))| ZoneRecord {
+                    id: i.to_string(),
+                    record,
+                    settings,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn drafts_the_config_that_produced_the_records() {
+        let original: Zone = serde_yaml::from_str(
+            "
+domain: example.com
+private_prefix: int
+records:
+  - type: TXT
+    value: v=spf1 -all
+networks:
+  - name: office
+    root: gw
+    proxied: true
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00::1
+        alias:
+          - name: www
+            proxied: false
+      - name: nas
+        private_ip: [10.0.0.2, 10.0.0.3]
+        alias: [files]
+        records:
+          - type: MX
+            priority: 10
+            value: nas.office.int.example.com
+",
+        )
+        .unwrap();
+        let mut records = original.records_with_settings(RecordTypeFilter::Both);
+        let manual = Record::A("vpn.example.com".to_string(), "192.0.2.1".to_string());
+        records.push((manual.clone(), RecordSettings::default()));
+        let live = live(records);
+
+        let zone = draft("example.com", &live).unwrap();
+
+        assert_eq!(
+            serde_yaml::to_string(&zone).unwrap(),
+            serde_yaml::to_string(&original).unwrap()
+        );
+        let unmapped = unmapped(&zone, &live);
+        assert_eq!(unmapped.superflous, RecordSet::group(vec![manual]));
+        assert_eq!(unmapped.len(), 1);
+    }
+
+    #[test]
+    fn private_only_zones_keep_their_backend() {
+        let live = live(vec![
+            (
+                Record::A(
+                    "gw.office.lan.example.com".to_string(),
+                    "10.0.0.1".to_string(),
+                ),
+                RecordSettings::new(Some(300), None),
+            ),
+            (
+                Record::A(
+                    "gw.office.int.example.com".to_string(),
+                    "10.0.0.1".to_string(),
+                ),
+                RecordSettings::new(Some(300), None),
+            ),
+        ]);
+
+        let zone = draft("example.com", &live).unwrap();
+
+        assert_eq!(zone.private_prefix, "int");
+        assert_eq!(zone.backends.cloudflare, RecordTypeFilter::Private);
+        assert_eq!(zone.ttl, Some(300));
+        assert!(draft("example.org", &live).is_err());
+    }
+}
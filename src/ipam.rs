@@ -0,0 +1,302 @@
+//! Allocation of private IPv4 addresses for servers that do not configure one.
+//! Allocations are kept in a lock file next to the config so they stay stable
+//! as servers are added and removed.
+use crate::model::Zone;
+use anyhow::{anyhow, Result};
+use ipnet::Ipv4Net;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+/// The allocated address of each server, by network and server name.
+pub type Allocations = BTreeMap<String, BTreeMap<String, Ipv4Addr>>;
+
+const LOCK_HEADER: &str = "# Addresses allocated by netmgr. Keep this file next to the config.\n";
+
+/// The lock file of the config at `config`, `config.yaml` is locked in `config.lock`.
+/// Each zone of a file listing several gets its own, like `config.example.com.lock`.
+pub fn lock_path(config: &Path, listed_zone: Option<&str>) -> PathBuf {
+    match listed_zone {
+        Some(domain) => config.with_extension(format!("{}.lock", domain)),
+        None => config.with_extension("lock"),
+    }
+}
+
+pub fn read_lock(path: &Path) -> Result<Allocations> {
+    if !path.exists() {
+        return Ok(Allocations::new());
+    }
+    let source = fs::read_to_string(path)?;
+    serde_yaml::from_str::<Option<Allocations>>(&source)
+        .map(Option::unwrap_or_default)
+        .map_err(|e| anyhow!("Unable to read {}: {}", path.display(), e))
+}
+
+pub fn write_lock(path: &Path, allocations: &Allocations) -> Result<()> {
+    fs::write(
+        path,
+        format!("{}{}", LOCK_HEADER, serde_yaml::to_string(allocations)?),
+    )?;
+    Ok(())
+}
+
+/// Gives every server without a `private_ip` an address from its network's
+/// IPv4 subnets. Servers keep their address from `locked` as long as it is
+/// still free and inside a subnet; the others get the lowest free address,
+/// in the order they are configured. Returns the allocations to lock.
+pub fn allocate(zone: &mut Zone, locked: &Allocations) -> Result<Allocations> {
+    let mut used: HashSet<Ipv4Addr> = zone
+        .networks
+        .iter()
+        .flat_map(|n| &n.servers)
+        .flat_map(|s| &s.private_ip)
+        .filter_map(|ip| ip.parse().ok())
+        .collect();
+    let mut allocations = Allocations::new();
+
+    for network in &mut zone.networks {
+        let subnets: Vec<Ipv4Net> = network
+            .subnet
+            .iter()
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        let empty = BTreeMap::new();
+        let previous = locked.get(&network.name).unwrap_or(&empty);
+        let mut allocated = BTreeMap::new();
+
+        // Keep the previous allocations first so new servers cannot take them.
+        for server in network.servers.iter().filter(|s| s.private_ip.is_empty()) {
+            if let Some(ip) = previous.get(&server.name) {
+                if !used.contains(ip) && subnets.iter().any(|net| is_host(net, ip)) {
+                    used.insert(*ip);
+                    allocated.insert(server.name.clone(), *ip);
+                }
+            }
+        }
+        for server in network.servers.iter().filter(|s| s.private_ip.is_empty()) {
+            if allocated.contains_key(&server.name) {
+                continue;
+            }
+            if subnets.is_empty() {
+                return Err(anyhow!(
+                    "Server {} has no private_ip and network {} has no IPv4 subnet to allocate one from",
+                    server.name,
+                    network.name
+                ));
+            }
+            let ip = subnets
+                .iter()
+                .flat_map(|net| net.hosts())
+                .find(|ip| !used.contains(ip))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No free addresses left in network {} for server {}",
+                        network.name,
+                        server.name
+                    )
+                })?;
+            used.insert(ip);
+            allocated.insert(server.name.clone(), ip);
+        }
+
+        for server in &mut network.servers {
+            if let Some(ip) = allocated.get(&server.name) {
+                server.private_ip = vec![ip.to_string()];
+            }
+        }
+        if !allocated.is_empty() {
+            allocations.insert(network.name.clone(), allocated);
+        }
+    }
+    Ok(allocations)
+}
+
+/// How much of an IPv4 subnet of a network is taken.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Usage {
+    pub network: String,
+    pub subnet: Ipv4Net,
+    pub used: u64,
+    pub free: u64,
+    pub next_free: Option<Ipv4Addr>,
+}
+
+/// Whether `ip` is one of `subnet.hosts()`, which leave out the network and
+/// broadcast address of subnets larger than a /31.
+fn is_host(subnet: &Ipv4Net, ip: &Ipv4Addr) -> bool {
+    subnet.contains(ip)
+        && (subnet.prefix_len() >= 31 || (*ip != subnet.network() && *ip != subnet.broadcast()))
+}
+
+/// The usage of every IPv4 subnet in `zone`, once its addresses are allocated.
+pub fn usage(zone: &Zone) -> Vec<Usage> {
+    let used: HashSet<Ipv4Addr> = zone
+        .networks
+        .iter()
+        .flat_map(|n| &n.servers)
+        .flat_map(|s| &s.private_ip)
+        .filter_map(|ip| ip.parse().ok())
+        .collect();
+    let mut usage = Vec::new();
+    for network in &zone.networks {
+        for subnet in network
+            .subnet
+            .iter()
+            .filter_map(|s| s.parse::<Ipv4Net>().ok())
+        {
+            // Like `hosts`, see `is_host`.
+            let hosts = match subnet.prefix_len() {
+                32 => 1,
+                31 => 2,
+                len => (1u64 << (32 - len)) - 2,
+            };
+            let taken = used.iter().filter(|ip| is_host(&subnet, ip)).count() as u64;
+            usage.push(Usage {
+                network: network.name.clone(),
+                subnet,
+                used: taken,
+                free: hosts - taken,
+                next_free: subnet.hosts().find(|ip| !used.contains(ip)),
+            });
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(servers: &str) -> Zone {
+        serde_yaml::from_str(&format!(
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    subnet: 10.0.0.0/29
+    servers:
+{}",
+            servers
+        ))
+        .unwrap()
+    }
+
+    fn addresses(zone: &Zone) -> Vec<(String, String)> {
+        zone.networks[0]
+            .servers
+            .iter()
+            .map(|s| (s.name.clone(), s.private_ip.join(",")))
+            .collect()
+    }
+
+    fn pair(server: &str, ip: &str) -> (String, String) {
+        (server.to_string(), ip.to_string())
+    }
+
+    #[test]
+    fn zones_of_a_list_get_their_own_lock() {
+        let config = Path::new("zones.yaml");
+        assert_eq!(lock_path(config, None), Path::new("zones.lock"));
+        assert_eq!(
+            lock_path(config, Some("example.com")),
+            Path::new("zones.example.com.lock")
+        );
+    }
+
+    #[test]
+    fn allocates_the_lowest_free_addresses() {
+        let mut zone = zone(
+            "      - name: gw
+        private_ip: 10.0.0.1
+      - name: nas
+      - name: ci
+",
+        );
+        let allocations = allocate(&mut zone, &Allocations::new()).unwrap();
+        assert_eq!(
+            addresses(&zone),
+            vec![
+                pair("gw", "10.0.0.1"),
+                pair("nas", "10.0.0.2"),
+                pair("ci", "10.0.0.3")
+            ]
+        );
+        assert_eq!(allocations["office"].len(), 2);
+    }
+
+    #[test]
+    fn locked_addresses_are_kept() {
+        let mut zone = zone(
+            "      - name: gw
+        private_ip: 10.0.0.1
+      - name: new
+      - name: ci
+",
+        );
+        let mut locked = Allocations::new();
+        locked.insert(
+            "office".to_string(),
+            [
+                ("nas".to_string(), Ipv4Addr::new(10, 0, 0, 2)),
+                ("ci".to_string(), Ipv4Addr::new(10, 0, 0, 3)),
+                ("gone".to_string(), Ipv4Addr::new(10, 0, 0, 4)),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let allocations = allocate(&mut zone, &locked).unwrap();
+        assert_eq!(
+            addresses(&zone),
+            vec![
+                pair("gw", "10.0.0.1"),
+                pair("new", "10.0.0.2"),
+                pair("ci", "10.0.0.3")
+            ]
+        );
+        assert!(!allocations["office"].contains_key("gone"));
+    }
+
+    #[test]
+    fn full_subnets_are_an_error() {
+        let servers: String = (0..7).map(|i| format!("      - name: s{}\n", i)).collect();
+        let err = allocate(&mut zone(&servers), &Allocations::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No free addresses left in network office for server s6"
+        );
+    }
+
+    #[test]
+    fn reports_the_usage_of_subnets() {
+        let mut office = zone(
+            "      - name: gw
+        private_ip: 10.0.0.1
+      - name: nas
+      - name: outside
+        private_ip: 192.168.0.1
+",
+        );
+        allocate(&mut office, &Allocations::new()).unwrap();
+        assert_eq!(
+            usage(&office),
+            vec![Usage {
+                network: "office".to_string(),
+                subnet: "10.0.0.0/29".parse().unwrap(),
+                used: 2,
+                free: 4,
+                next_free: Some(Ipv4Addr::new(10, 0, 0, 3)),
+            }]
+        );
+
+        let servers: String = (0..6).map(|i| format!("      - name: s{}\n", i)).collect();
+        let mut full = zone(&servers);
+        allocate(&mut full, &Allocations::new()).unwrap();
+        let usage = usage(&full);
+        assert_eq!(
+            (usage[0].used, usage[0].free, usage[0].next_free),
+            (6, 0, None)
+        );
+    }
+}
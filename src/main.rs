@@ -1,182 +1,647 @@
+#[macro_use]
+mod cli;
+mod daemon;
+mod error;
+mod export;
+mod import;
+mod ipam;
+mod metrics;
+#[cfg(test)]
+mod mock;
 mod model;
+mod plan;
+mod provider;
+mod reverse;
+mod serve;
+mod validate;
 use anyhow::{anyhow, Result};
-use cloudflare::endpoints::{dns, zone};
-use cloudflare::framework::{
-    apiclient::ApiClient,
-    auth::Credentials,
-    Environment, HttpApiClient, HttpApiClientConfig,
-};
-use serde::Deserialize;
-use std::collections::HashMap;
+use cli::{Cli, Command, ExportFormat};
+use error::Error;
+use model::{Record, RecordSet, RecordSettings, RecordTypeFilter, Zone};
+use plan::{Baseline, Diff, Plan};
+use provider::{Cloudflare, DnsProvider, LiveState};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    cloudflare_token: String,
+/// The exit code of `netmgr check` when Cloudflare does not match the config.
+const DRIFT_EXIT_CODE: i32 = 2;
+
+fn main() -> Result<()> {
+    let cli = Cli::parse()?;
+    match &cli.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Command::Validate => {
+            let files = model::config_files(&cli.config)?;
+            let mut errors = 0;
+            for file in &files {
+                let problems = validate::validate_file(file)?;
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                errors += validate::errors(&problems);
+            }
+            if errors > 0 {
+                return Err(Error::Validation(format!("Found {} problems", errors)).into());
+            }
+            allocate(&cli, &files)?;
+            println!("{} is valid.", cli.config.display());
+            Ok(())
+        }
+        Command::Export {
+            format: ExportFormat::Reverse,
+            out: Some(dir),
+        } => {
+            fs::create_dir_all(dir)?;
+            for (origin, file) in export::reverse_zone_files(&read_zone(&cli)?) {
+                let path = dir.join(format!("{}.zone", origin));
+                fs::write(&path, file)?;
+                verbose!("Wrote {}", path.display());
+            }
+            Ok(())
+        }
+        Command::Export { format, out } => {
+            let rendered = export::render(&read_zone(&cli)?, format)?;
+            match out {
+                Some(out) => fs::write(out, rendered)?,
+                None => print!("{}", rendered),
+            }
+            Ok(())
+        }
+        Command::Import { out } => {
+            let domain = cli
+                .zone
+                .as_ref()
+                .ok_or_else(|| anyhow!("Select the zone to import with --zone"))?;
+            let provider = Cloudflare::from_env()?;
+            let zone_id = provider.find_zone(domain)?;
+            let live = LiveState::fetch(&provider, &zone_id)?;
+            let zone = import::draft(domain, &live)?;
+            let yaml = serde_yaml::to_string(&zone)?;
+            match out {
+                Some(out) => fs::write(out, yaml)?,
+                None => print!("{}", yaml),
+            }
+            let unmapped = import::unmapped(&zone, &live);
+            if !unmapped.is_empty() {
+                eprintln!(
+                    "\nThe draft does not reproduce these records, review them before running `netmgr plan`:"
+                );
+                eprint!("{}", unmapped);
+            }
+            Ok(())
+        }
+        Command::Diff => {
+            let provider = Cloudflare::from_env()?;
+            let targets = read_targets(&cli)?;
+            let several = targets.len() > 1;
+            let mut results = Vec::new();
+            for (domain, desired) in targets {
+                if several {
+                    println!("{}:", domain);
+                }
+                let result = diff(&provider, &domain, desired).map(|diff| {
+                    if diff.is_empty() {
+                        println!("No differences.");
+                        return "no differences".to_string();
+                    }
+                    print!("{}", diff);
+                    format!("{} differences", diff.len())
+                });
+                results.push((domain, result));
+            }
+            summarize(results)
+        }
+        Command::Check => {
+            let provider = Cloudflare::from_env()?;
+            match check(&provider, read_targets(&cli)?) {
+                0 => Ok(()),
+                status => process::exit(status),
+            }
+        }
+        Command::Apply {
+            plan: Some(path), ..
+        } => {
+            let mut plans = Plan::read_all(path)?;
+            if let Some(domain) = &cli.zone {
+                let domains: Vec<String> = plans.iter().map(|p| p.domain.clone()).collect();
+                plans.retain(|p| &p.domain == domain);
+                if plans.is_empty() {
+                    return Err(anyhow!(
+                        "{} holds no plan for {}, only for {}",
+                        path.display(),
+                        domain,
+                        domains.join(", ")
+                    ));
+                }
+            }
+            let provider = Cloudflare::from_env()?;
+            let several = plans.len() > 1;
+            let mut results = Vec::new();
+            for plan in plans {
+                let domain = plan.domain.clone();
+                if several {
+                    println!("{}:", domain);
+                }
+                results.push((domain, apply_saved(&provider, plan)));
+            }
+            summarize(results)
+        }
+        Command::Serve { listen } => {
+            let authority = serve::Authority::new(&read_zone(&cli)?);
+            let server = serve::Server::bind(*listen)?;
+            println!(
+                "Serving {} on {} (UDP and TCP)",
+                authority.origin(),
+                server.local_addr()?
+            );
+            server.run(Arc::new(authority))
+        }
+        Command::Daemon { interval, metrics } => {
+            let registry = Arc::new(metrics::Metrics::default());
+            let retried = registry.clone();
+            let cloudflare =
+                Cloudflare::from_env()?.on_retry(move |endpoint| retried.api_error(endpoint));
+            if let Some(addr) = metrics {
+                let server = metrics::Server::bind(*addr)?;
+                println!("Serving metrics on {}", server.local_addr()?);
+                server.spawn(registry.clone());
+            }
+            let provider = metrics::Metered {
+                provider: &cloudflare,
+                metrics: &registry,
+            };
+            println!(
+                "Syncing {} every {}s",
+                cli.config.display(),
+                interval.as_secs()
+            );
+            daemon::run(&cli.config, *interval, &registry, || {
+                sync(&cli, &provider, &registry)
+            })
+        }
+        Command::IpamList => {
+            let zones = read_zones(&cli)?;
+            let several = zones.len() > 1;
+            let mut rows = Vec::new();
+            for (zone, allocated) in &zones {
+                for network in &zone.networks {
+                    for server in &network.servers {
+                        let allocated = allocated
+                            .get(&network.name)
+                            .is_some_and(|n| n.contains_key(&server.name));
+                        for ip in server.private_ip.iter().chain(&server.private_ipv6) {
+                            let source = if allocated && !ip.contains(':') {
+                                "allocated"
+                            } else {
+                                "static"
+                            };
+                            let mut row = Vec::new();
+                            if several {
+                                row.push(zone.domain.clone());
+                            }
+                            row.extend([&network.name, &server.name, ip].map(String::clone));
+                            row.push(source.to_string());
+                            rows.push(row);
+                        }
+                    }
+                }
+            }
+            print_columns(&rows);
+
+            let mut subnets = Vec::new();
+            for (zone, _) in &zones {
+                for usage in ipam::usage(zone) {
+                    let mut row = Vec::new();
+                    if several {
+                        row.push(zone.domain.clone());
+                    }
+                    row.extend([
+                        usage.network,
+                        usage.subnet.to_string(),
+                        format!("{} used", usage.used),
+                        format!("{} free", usage.free),
+                        usage
+                            .next_free
+                            .map_or("full".to_string(), |ip| format!("next {}", ip)),
+                    ]);
+                    subnets.push(row);
+                }
+            }
+            if !subnets.is_empty() {
+                println!();
+                print_columns(&subnets);
+            }
+            Ok(())
+        }
+        Command::Plan { .. } | Command::Apply { .. } => {
+            let provider = Cloudflare::from_env()?;
+            let targets = read_targets(&cli)?;
+            let several = targets.len() > 1;
+            let mut staged = Vec::new();
+            for (domain, desired) in targets {
+                if several {
+                    println!("{}:", domain);
+                }
+                let stage = stage(&provider, &domain, desired);
+                if let Ok((_, _, plan)) = &stage {
+                    print!("{}", plan);
+                }
+                staged.push((domain, stage));
+            }
+            let plans = || staged.iter().filter_map(|(_, s)| s.as_ref().ok());
+
+            if let Command::Plan { out } = &cli.command {
+                if let Some(out) = out {
+                    let plans: Vec<&Plan> = plans().map(|(_, _, plan)| plan).collect();
+                    Plan::write_all(&plans, out)?;
+                    println!(
+                        "\nSaved the plan to {0}, apply it with `netmgr apply {0}`",
+                        out.display()
+                    );
+                }
+                let results = staged
+                    .into_iter()
+                    .map(|(domain, stage)| (domain, stage.map(|(_, _, plan)| plan.summary())))
+                    .collect();
+                return summarize(results);
+            }
+            if plans().any(|(_, _, plan)| !plan.is_empty())
+                && !matches!(cli.command, Command::Apply { yes: true, .. })
+                && !confirm()?
+            {
+                println!("Apply cancelled.");
+                return Ok(());
+            }
+            let results = staged
+                .into_iter()
+                .map(|(domain, stage)| {
+                    let result = stage.and_then(|(zone_id, live, plan)| {
+                        let summary = plan.applied_summary();
+                        if !plan.is_empty() {
+                            provider::apply(&provider, &zone_id, &live, plan)?;
+                        }
+                        Ok(summary)
+                    });
+                    (domain, result)
+                })
+                .collect();
+            summarize(results)
+        }
+    }
 }
 
-fn cf_record_to_record(cf: &dns::DnsRecord) -> Option<model::Record> {
-    let name = cf.name.to_string();
-    match &cf.content {
-        dns::DnsContent::A { content } => Some(model::Record::A(name, content.to_string())),
-        dns::DnsContent::CNAME { content } => Some(model::Record::Cname(name, content.to_string())),
-        _ => None,
+/// Computes the plan for one zone along with the live state it is based on.
+fn stage(
+    provider: &dyn DnsProvider,
+    domain: &str,
+    desired: Vec<RecordSet>,
+) -> Result<(String, LiveState, Plan)> {
+    let zone_id = provider.find_zone(domain)?;
+    let live = LiveState::fetch(provider, &zone_id)?;
+    let plan = Plan::new(domain, live.record_sets(), desired, &live.owned);
+    Ok((zone_id, live, plan))
+}
+
+/// Applies the plan of every zone without asking, as `netmgr daemon` does.
+fn sync(cli: &Cli, provider: &dyn DnsProvider, metrics: &metrics::Metrics) -> Result<()> {
+    let targets = read_targets(cli)?;
+    let total = targets.len();
+    let mut failed = 0;
+    for (domain, desired) in targets {
+        let result = stage(provider, &domain, desired).and_then(|(zone_id, live, plan)| {
+            metrics.drift(&domain, plan.changes.len());
+            if plan.is_empty() {
+                verbose!("{}: in sync", domain);
+                return Ok(());
+            }
+            let summary = plan.applied_summary();
+            print!("{}:\n{}", domain, plan);
+            provider::apply_counted(provider, &zone_id, &live, plan, |change| {
+                metrics.applied(change)
+            })?;
+            println!("{}: {}", domain, summary);
+            Ok(())
+        });
+        if let Err(e) = result {
+            failed += 1;
+            eprintln!("{}: failed: {}", domain, e);
+        }
     }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} zones failed", failed, total));
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct Diff {
-    superflous: Vec<model::Record>,
-    pub missing: Vec<model::Record>,
-    pub changed: Vec<(model::Record, model::Record)>,
+/// Compares every target with the live zone and returns the exit status of
+/// `netmgr check`: 0 when all are in sync, `DRIFT_EXIT_CODE` when any drifted
+/// and 1 when any could not be checked.
+fn check(provider: &dyn DnsProvider, targets: Vec<(String, Vec<RecordSet>)>) -> i32 {
+    let (mut drifted, mut failed) = (0, 0);
+    for (domain, desired) in targets {
+        match stage(provider, &domain, desired) {
+            Ok((_, _, plan)) if plan.is_empty() => println!("{}: in sync", domain),
+            Ok((_, _, plan)) => {
+                drifted += 1;
+                println!("{}: drifted, {}", domain, plan.summary());
+                for change in &plan.changes {
+                    println!("{}", change);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{}: failed: {}", domain, e);
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("Error: Unable to check {} zones", failed);
+        return 1;
+    }
+    if drifted > 0 {
+        return DRIFT_EXIT_CODE;
+    }
+    0
 }
 
-impl Diff {
-    pub fn new(a: Vec<model::Record>, b: Vec<model::Record>) -> Self {
-        let mut superflous = Vec::new();
-        let mut missing = Vec::new();
-        let mut changed = Vec::new();
+fn diff(provider: &dyn DnsProvider, domain: &str, desired: Vec<RecordSet>) -> Result<Diff> {
+    let zone_id = provider.find_zone(domain)?;
+    let live = LiveState::fetch(provider, &zone_id)?;
+    Ok(Diff::new(live.record_sets(), desired))
+}
 
-        let a: HashMap<String, model::Record> = a.into_iter().map(|r| (r.name(), r)).collect();
-        let b: HashMap<String, model::Record> = b.into_iter().map(|r| (r.name(), r)).collect();
+/// Applies a saved plan unless its zone changed since the plan was made.
+fn apply_saved(provider: &dyn DnsProvider, plan: Plan) -> Result<String> {
+    let zone_id = provider.find_zone(&plan.domain)?;
+    let live = LiveState::fetch(provider, &zone_id)?;
+    plan.check_drift(&Baseline::new(live.record_sets(), &live.owned))?;
+    print!("{}", plan);
+    let summary = plan.applied_summary();
+    provider::apply(provider, &zone_id, &live, plan)?;
+    Ok(summary)
+}
 
-        for (name, record) in a.iter() {
-            if !b.contains_key(name) {
-                superflous.push(record.clone());
-            } else if record != b.get(name).unwrap() {
-                changed.push((b.get(name).unwrap().clone(), record.clone()));
+/// Prints how every zone fared when there were several. Fails when any of
+/// them failed, with its own error if it was the only zone.
+fn summarize(results: Vec<(String, Result<String>)>) -> Result<()> {
+    if results.len() == 1 {
+        return results.into_iter().next().unwrap().1.map(|_| ());
+    }
+    println!("\nSummary:");
+    let width = results.iter().map(|(domain, _)| domain.len()).max();
+    let width = width.unwrap_or(0);
+    let mut failed = 0;
+    for (domain, result) in &results {
+        match result {
+            Ok(summary) => println!("  {:width$}  {}", domain, summary),
+            Err(e) => {
+                failed += 1;
+                println!("  {:width$}  failed: {}", domain, e);
             }
         }
-        for (name, record) in b.iter() {
-            if !a.contains_key(name) {
-                missing.push(record.clone());
-            }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} zones failed", failed, results.len()));
+    }
+    Ok(())
+}
+
+/// Reads the zones in every config file, or only the one selected with
+/// `--zone`, along with the addresses allocated to their servers.
+fn read_zones(cli: &Cli) -> Result<Vec<(Zone, ipam::Allocations)>> {
+    let mut zones = read_all_zones(cli)?;
+    if let Some(domain) = &cli.zone {
+        zones.retain(|(zone, _)| &zone.domain == domain);
+        if zones.is_empty() {
+            return Err(missing_zone(cli, domain));
         }
-        Diff {
-            superflous,
-            missing,
-            changed,
+    }
+    Ok(zones)
+}
+
+fn missing_zone(cli: &Cli, domain: &str) -> anyhow::Error {
+    anyhow!(
+        "{} does not contain the zone {}",
+        cli.config.display(),
+        domain
+    )
+}
+
+/// Reads the zones in every config file whatever `--zone` selects.
+fn read_all_zones(cli: &Cli) -> Result<Vec<(Zone, ipam::Allocations)>> {
+    let files = model::config_files(&cli.config)?;
+    for file in &files {
+        verbose!("Reading {}", file.display());
+        let problems = validate::validate_file(file)?;
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        let errors = validate::errors(&problems);
+        if errors > 0 {
+            return Err(Error::Validation(format!(
+                "Found {} problems in {}, see `netmgr validate`",
+                errors,
+                file.display()
+            ))
+            .into());
         }
     }
+    allocate(cli, &files)
 }
 
-impl From<model::Record> for dns::DnsContent {
-    fn from(r: model::Record) -> Self {
-        match r {
-            model::Record::A(_name, ip) => dns::DnsContent::A {
-                content: ip.parse().unwrap(),
-            },
-            model::Record::Cname(_name, cname) => dns::DnsContent::CNAME { content: cname },
+/// Parses the zones in `files`, which have been validated already, and
+/// allocates the missing addresses of their servers. Only the commands
+/// publishing the records update the lock files.
+fn allocate(cli: &Cli, files: &[PathBuf]) -> Result<Vec<(Zone, ipam::Allocations)>> {
+    let persist = matches!(
+        cli.command,
+        Command::Plan { .. } | Command::Apply { .. } | Command::Daemon { .. }
+    );
+    let mut zones: Vec<(Zone, ipam::Allocations)> = Vec::new();
+    for file in files {
+        let source = fs::read_to_string(file)?;
+        let listed = model::lists_zones(&source);
+        for mut zone in Zone::parse_all(&source)? {
+            if zones.iter().any(|(z, _)| z.domain == zone.domain) {
+                return Err(anyhow!(
+                    "The zone {} is configured more than once",
+                    zone.domain
+                ));
+            }
+            let lock = ipam::lock_path(file, listed.then_some(zone.domain.as_str()));
+            let locked = ipam::read_lock(&lock)?;
+            let allocations = ipam::allocate(&mut zone, &locked)?;
+            if persist && allocations != locked && (lock.exists() || !allocations.is_empty()) {
+                ipam::write_lock(&lock, &allocations)?;
+                verbose!("Updated the allocated addresses in {}", lock.display());
+            }
+            zones.push((zone, allocations));
         }
     }
+    Ok(zones)
 }
-fn main() -> Result<()> {
-    let env: &'static Config = Box::leak(Box::new(envy::from_env::<Config>()?));
 
-    let zone = model::Zone::read("./config.yaml")?;
-    let recs = zone.all_records();
+fn zones(cli: &Cli) -> Result<Vec<Zone>> {
+    Ok(read_zones(cli)?.into_iter().map(|(zone, _)| zone).collect())
+}
 
-    let api_client = get_api_client(env)?;
-    let zone_identifier = find_zone_id(&api_client, zone)?;
-    let (record_ids, cf_recs) = get_current_records(&api_client, &zone_identifier)?;
+/// The record sets of every Cloudflare zone `cli` selects.
+fn read_targets(cli: &Cli) -> Result<Vec<(String, Vec<RecordSet>)>> {
+    let zones: Vec<Zone> = read_all_zones(cli)?
+        .into_iter()
+        .map(|(zone, _)| zone)
+        .collect();
+    targets(cli, &zones)
+}
 
-    let d = Diff::new(cf_recs, recs);
+/// Reads the zone for the commands that work on a single one.
+fn read_zone(cli: &Cli) -> Result<Zone> {
+    let mut zones = zones(cli)?;
+    if zones.len() > 1 {
+        return Err(anyhow!(
+            "{} contains {} zones, select one with --zone",
+            cli.config.display(),
+            zones.len()
+        ));
+    }
+    if zones.is_empty() {
+        return Err(anyhow!("{} contains no zones", cli.config.display()));
+    }
+    Ok(zones.remove(0))
+}
 
-    for (a, _b) in d.changed {
-        let resp = update_record(&zone_identifier, &record_ids, a, &api_client)?;
-        println!("{:?}", resp);
+/// The Cloudflare zones to publish to and the record sets each should hold,
+/// or only the one selected with `--zone`, forward or reverse.
+/// `--public-only` overrides the records configured for the forward zones.
+/// A reverse zone shared by several zones gets the PTR records of all of them,
+/// so `zones` must hold every configured zone.
+fn targets(cli: &Cli, zones: &[Zone]) -> Result<Vec<(String, Vec<RecordSet>)>> {
+    let mut targets = Vec::new();
+    let mut reverse: BTreeMap<String, Vec<(Record, RecordSettings)>> = BTreeMap::new();
+    for zone in zones {
+        let filter = if cli.public_only {
+            RecordTypeFilter::Public
+        } else {
+            zone.backends.cloudflare
+        };
+        targets.push((zone.domain.clone(), zone.record_sets(filter)));
+        if zone.backends.cloudflare_reverse {
+            let settings = RecordSettings::new(zone.ttl, None);
+            for r in reverse::reverse_zones(zone) {
+                let records = reverse.entry(r.origin).or_default();
+                records.extend(r.records.into_iter().map(|r| (r, settings)));
+            }
+        }
     }
-    for record in d.missing {
-        let resp = create_record(&zone_identifier, record, &api_client)?;
-        println!("{:?}", resp);
+    targets.extend(
+        reverse
+            .into_iter()
+            .map(|(origin, records)| (origin, RecordSet::group_with(records))),
+    );
+    if let Some(domain) = &cli.zone {
+        targets.retain(|(origin, _)| origin == domain);
+        if targets.is_empty() {
+            return Err(missing_zone(cli, domain));
+        }
     }
-    Ok(())
+    Ok(targets)
 }
 
-fn create_record(
-    zone_identifier: &String,
-    record: model::Record,
-    api_client: &HttpApiClient,
-) -> Result<cloudflare::framework::response::ApiSuccess<dns::DnsRecord>, anyhow::Error> {
-    let req = dns::CreateDnsRecord {
-        zone_identifier: zone_identifier,
-        params: dns::CreateDnsRecordParams {
-            ttl: None,
-            priority: None,
-            proxied: Some(false),
-            name: &record.name(),
-            content: record.into(),
-        },
-    };
-    let resp = api_client.request(&req)?;
-    Ok(resp)
-}
-
-fn get_api_client(env: &Config) -> Result<HttpApiClient, anyhow::Error> {
-    let credentials = Credentials::UserAuthToken {
-        token: env.cloudflare_token.to_string(),
-    };
-    let api_client = HttpApiClient::new(
-        credentials,
-        HttpApiClientConfig::default(),
-        Environment::Production,
-    )?;
-    Ok(api_client)
-}
-
-fn get_current_records(
-    api_client: &HttpApiClient,
-    zone_identifier: &str,
-) -> Result<(HashMap<String, String>, Vec<model::Record>), anyhow::Error> {
-    let list_dns_records = &dns::ListDnsRecords {
-        zone_identifier: &zone_identifier,
-        params: Default::default(),
-    };
-    let dns_records = api_client.request(list_dns_records)?.result;
-    let record_ids: HashMap<String, String> = dns_records
-        .iter()
-        .map(|r| (r.name.to_string(), r.id.to_string()))
+/// Prints `rows` with every column but the last padded to the same width.
+fn print_columns(rows: &[Vec<String>]) {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or(0))
         .collect();
-    let cf_recs: Vec<model::Record> = dns_records.iter().flat_map(cf_record_to_record).collect();
-    Ok((record_ids, cf_recs))
+    for row in rows {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            if i + 1 < row.len() {
+                line.push_str(&format!("{:width$}  ", cell, width = widths[i]));
+            } else {
+                line.push_str(cell);
+            }
+        }
+        println!("{}", line);
+    }
 }
 
-fn find_zone_id(api_client: &HttpApiClient, zone: model::Zone) -> Result<String> {
-    let z = &zone::ListZones {
-        params: Default::default(),
-    };
-    let zones = api_client.request(z)?.result;
-    let cf_zone = zones
-        .into_iter()
-        .find(|z| z.name == zone.domain)
-        .ok_or(anyhow!("Unable to find the zone in your account"))?;
-    Ok(cf_zone.id)
-}
-
-fn update_record(
-    zone_identifier: &str,
-    record_ids: &HashMap<String, String>,
-    new_value: model::Record,
-    api_client: &HttpApiClient,
-) -> Result<dns::DnsRecord> {
-    let update_dns_record = &dns::UpdateDnsRecord {
-        zone_identifier: zone_identifier,
-        identifier: record_ids
-            .get(&new_value.name())
-            .ok_or(anyhow!("Unable to find record id"))?,
-        params: dns::UpdateDnsRecordParams {
-            proxied: Some(false),
-            ttl: None,
-            name: &new_value.name(),
-            content: match new_value {
-                model::Record::A(..) => dns::DnsContent::A {
-                    content: new_value.value().parse()?,
-                },
-                model::Record::Cname(..) => dns::DnsContent::CNAME {
-                    content: new_value.value(),
-                },
-            },
-        },
-    };
-    let resp = api_client.request(update_dns_record)?.result;
-    Ok(resp)
+fn confirm() -> Result<bool> {
+    print!("\nDo you want to perform these actions? Only 'yes' will be accepted: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use provider::memory::InMemory;
+
+    const ZONES: &str = "zones:
+  - domain: a.com
+    private_prefix: int
+    backends:
+      cloudflare_reverse: true
+    networks:
+      - name: office
+        root: gw
+        subnet: 10.0.0.0/24
+        servers:
+          - name: gw
+            private_ip: 10.0.0.1
+  - domain: b.com
+    private_prefix: int
+    backends:
+      cloudflare_reverse: true
+    networks:
+      - name: lab
+        root: nas
+        subnet: 10.0.0.0/24
+        servers:
+          - name: nas
+            private_ip: 10.0.0.2
+";
+
+    fn select(zone: &str) -> Result<Vec<(String, Vec<RecordSet>)>> {
+        let cli = Cli::parse_from(["--zone", zone, "plan"].map(String::from)).unwrap();
+        targets(&cli, &Zone::parse_all(ZONES).unwrap())
+    }
+
+    #[test]
+    fn shared_reverse_zones_are_built_from_every_zone() {
+        let forward = select("a.com").unwrap();
+        assert_eq!(forward.len(), 1);
+        assert_eq!(forward[0].0, "a.com");
+
+        let reverse = select("0.0.10.in-addr.arpa").unwrap();
+        assert_eq!(reverse.len(), 1);
+        let names: Vec<&str> = reverse[0].1.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["1.0.0.10.in-addr.arpa", "2.0.0.10.in-addr.arpa"]
+        );
+        assert!(select("c.com").is_err());
+    }
+
+    #[test]
+    fn check_exits_with_the_state_of_the_zones() {
+        let provider = InMemory::new("a.com");
+        assert_eq!(check(&provider, select("a.com").unwrap()), DRIFT_EXIT_CODE);
+
+        let (domain, desired) = select("a.com").unwrap().remove(0);
+        let (zone_id, live, plan) = stage(&provider, &domain, desired).unwrap();
+        provider::apply(&provider, &zone_id, &live, plan).unwrap();
+        assert_eq!(check(&provider, select("a.com").unwrap()), 0);
+
+        assert_eq!(check(&provider, select("b.com").unwrap()), 1);
+    }
 }
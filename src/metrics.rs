@@ -0,0 +1,308 @@
+//! Prometheus metrics and health checks for `netmgr daemon`.
+use crate::model::{Record, RecordSettings};
+use crate::plan::Change;
+use crate::provider::{DnsProvider, ZoneRecord};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+struct State {
+    created: u64,
+    updated: u64,
+    deleted: u64,
+    api_errors: BTreeMap<&'static str, u64>,
+    last_sync: Option<SystemTime>,
+    ready: bool,
+    drift: BTreeMap<String, usize>,
+}
+
+/// What the daemon did so far, shared with the HTTP server.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+impl Metrics {
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.state.lock().unwrap())
+    }
+
+    /// Records the outcome of a sync. The daemon is ready while its last sync succeeded.
+    pub fn synced(&self, ok: bool) {
+        self.update(|s| {
+            s.ready = ok;
+            if ok {
+                s.last_sync = Some(SystemTime::now());
+            }
+        })
+    }
+
+    /// Records a change applied to a zone.
+    pub fn applied(&self, change: &Change) {
+        self.update(|s| match change {
+            Change::Create { .. } => s.created += 1,
+            Change::Update { .. } => s.updated += 1,
+            Change::Delete { .. } => s.deleted += 1,
+        })
+    }
+
+    /// Records a failed API call to `endpoint`, a `DnsProvider` method.
+    pub fn api_error(&self, endpoint: &'static str) {
+        self.update(|s| *s.api_errors.entry(endpoint).or_default() += 1)
+    }
+
+    /// Records the number of changes a sync found pending in `zone`.
+    pub fn drift(&self, zone: &str, changes: usize) {
+        self.update(|s| {
+            s.drift.insert(zone.to_string(), changes);
+        })
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().ready
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let s = self.state.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "{}{} {}", name, labels, value).unwrap();
+            }
+        };
+        let counter = |value: u64| vec![(String::new(), value.to_string())];
+        metric(
+            "netmgr_records_created_total",
+            "counter",
+            "Record sets created in Cloudflare.",
+            counter(s.created),
+        );
+        metric(
+            "netmgr_records_updated_total",
+            "counter",
+            "Record sets updated in Cloudflare.",
+            counter(s.updated),
+        );
+        metric(
+            "netmgr_records_deleted_total",
+            "counter",
+            "Record sets deleted from Cloudflare.",
+            counter(s.deleted),
+        );
+        metric(
+            "netmgr_api_errors_total",
+            "counter",
+            "Failed Cloudflare API calls by endpoint, including the retried ones.",
+            s.api_errors
+                .iter()
+                .map(|(endpoint, n)| (format!("{{endpoint=\"{}\"}}", endpoint), n.to_string()))
+                .collect(),
+        );
+        let last_sync = s
+            .last_sync
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        metric(
+            "netmgr_last_sync_timestamp_seconds",
+            "gauge",
+            "Unix time of the last successful sync.",
+            counter(last_sync),
+        );
+        metric(
+            "netmgr_zone_drift",
+            "gauge",
+            "Changes the last sync found pending in the zone.",
+            s.drift
+                .iter()
+                .map(|(zone, n)| (format!("{{zone=\"{}\"}}", zone), n.to_string()))
+                .collect(),
+        );
+        out
+    }
+}
+
+/// A provider counting the calls that failed.
+pub struct Metered<'a> {
+    pub provider: &'a dyn DnsProvider,
+    pub metrics: &'a Metrics,
+}
+
+impl Metered<'_> {
+    fn count<T>(&self, endpoint: &'static str, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.metrics.api_error(endpoint);
+        }
+        result
+    }
+}
+
+impl DnsProvider for Metered<'_> {
+    fn find_zone(&self, domain: &str) -> Result<String> {
+        self.count("find_zone", self.provider.find_zone(domain))
+    }
+
+    fn list_records(&self, zone_id: &str) -> Result<Vec<ZoneRecord>> {
+        self.count("list_records", self.provider.list_records(zone_id))
+    }
+
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<String> {
+        let result = self.provider.create_record(zone_id, record, settings);
+        self.count("create_record", result)
+    }
+
+    fn update_record(
+        &self,
+        zone_id: &str,
+        id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<()> {
+        let result = self.provider.update_record(zone_id, id, record, settings);
+        self.count("update_record", result)
+    }
+
+    fn delete_record(&self, zone_id: &str, id: &str) -> Result<()> {
+        self.count("delete_record", self.provider.delete_record(zone_id, id))
+    }
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` over HTTP.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr) -> Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Answers requests one at a time until accepting a connection fails.
+    pub fn run(self, metrics: Arc<Metrics>) -> Result<()> {
+        for stream in self.listener.incoming() {
+            if let Err(e) = respond(stream?, &metrics) {
+                verbose!("Metrics request failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(self, metrics: Arc<Metrics>) {
+        thread::spawn(move || {
+            if let Err(e) = self.run(metrics) {
+                eprintln!("The metrics server stopped: {}", e);
+            }
+        });
+    }
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers, nothing in them matters here.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", metrics.render()),
+        "/healthz" => ("200 OK", "ok\n".to_string()),
+        "/readyz" if metrics.is_ready() => ("200 OK", "ready\n".to_string()),
+        "/readyz" => ("503 Service Unavailable", "not ready\n".to_string()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::RecordSet;
+    use crate::provider::memory::InMemory;
+    use std::io::Read;
+
+    #[test]
+    fn counts_applied_changes_and_failed_calls() {
+        let provider = InMemory::new("example.com");
+        let metrics = Metrics::default();
+        let metered = Metered {
+            provider: &provider,
+            metrics: &metrics,
+        };
+        let zone_id = metered.find_zone("example.com").unwrap();
+        let record = Record::A("gw.example.com".to_string(), "10.0.0.1".to_string());
+        let id = metered
+            .create_record(&zone_id, &record, RecordSettings::default())
+            .unwrap();
+        metered.delete_record(&zone_id, &id).unwrap();
+        assert!(metered.find_zone("example.org").is_err());
+        let set = RecordSet::group(vec![record]).remove(0);
+        metrics.applied(&Change::Create { set: set.clone() });
+        metrics.applied(&Change::Delete { set });
+        metrics.drift("example.com", 2);
+
+        let rendered = metrics.render();
+        for line in [
+            "netmgr_records_created_total 1",
+            "netmgr_records_updated_total 0",
+            "netmgr_records_deleted_total 1",
+            "netmgr_api_errors_total{endpoint=\"find_zone\"} 1",
+            "netmgr_last_sync_timestamp_seconds 0",
+            "netmgr_zone_drift{zone=\"example.com\"} 2",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "{}", line);
+        }
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics_and_health_checks() {
+        let server = Server::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        server.spawn(metrics.clone());
+
+        assert!(get(addr, "/healthz").starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/readyz").starts_with("HTTP/1.1 503"));
+        metrics.synced(true);
+        assert!(get(addr, "/readyz").starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/metrics").contains("netmgr_records_created_total 0"));
+        assert!(get(addr, "/other").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,201 @@
+//! A minimal HTTP server standing in for the Cloudflare API in tests.
+use cloudflare::framework::{auth::Credentials, Environment, HttpApiClient, HttpApiClientConfig};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: String,
+}
+
+type Handler = dyn Fn(&Request) -> (u16, Value) + Send + Sync;
+
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn start<F>(handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> (u16, Value) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/client/v4/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let handler: Arc<Handler> = Arc::new(handler);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => break,
+                };
+                let request = match read_request(&mut stream) {
+                    Some(r) => r,
+                    None => continue,
+                };
+                let (status, body) = handler(&request);
+                log.lock().unwrap().push(request);
+                let body = body.to_string();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        MockServer { url, requests }
+    }
+
+    pub fn client(&self) -> HttpApiClient {
+        client(&self.url)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A client sending its requests to the API at `url`.
+pub fn client(url: &str) -> HttpApiClient {
+    HttpApiClient::new(
+        Credentials::UserAuthToken {
+            token: "test".to_string(),
+        },
+        HttpApiClientConfig::default(),
+        Environment::Custom(url::Url::parse(url).unwrap()),
+    )
+    .unwrap()
+}
+
+fn read_request<S: Read>(stream: &mut S) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                content_length = v.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Some(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+/// Wraps `items` in a Cloudflare list response for the given page.
+pub fn page(items: Vec<Value>, page: usize, per_page: usize, total_count: usize) -> Value {
+    json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result_info": {
+            "page": page,
+            "per_page": per_page,
+            "count": items.len(),
+            "total_count": total_count,
+            "total_pages": total_count.div_ceil(per_page),
+        },
+        "result": items,
+    })
+}
+
+/// Slices `items` according to the `page` and `per_page` query parameters of `req`.
+pub fn paginate(req: &Request, items: &[Value]) -> Value {
+    let page_no: usize = req
+        .query
+        .get("page")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(1);
+    let per_page: usize = req
+        .query
+        .get("per_page")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(20);
+    let chunk = items
+        .iter()
+        .skip((page_no - 1) * per_page)
+        .take(per_page)
+        .cloned()
+        .collect();
+    page(chunk, page_no, per_page, items.len())
+}
+
+pub fn success(result: Value) -> Value {
+    json!({"success": true, "errors": [], "messages": [], "result": result})
+}
+
+pub fn zone(id: &str, name: &str) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "account": {"id": "account", "name": "Account"},
+        "created_on": "2022-01-01T00:00:00Z",
+        "development_mode": 0,
+        "meta": {
+            "custom_certificate_quota": 0,
+            "page_rule_quota": 3,
+            "phishing_detected": false,
+            "multiple_railguns_allowed": false
+        },
+        "modified_on": "2022-01-01T00:00:00Z",
+        "name_servers": ["a.ns.cloudflare.com"],
+        "owner": {"type": "user", "id": "owner", "email": "owner@example.com"},
+        "paused": false,
+        "permissions": [],
+        "status": "active",
+        "type": "full"
+    })
+}
+
+pub fn dns_record(id: &str, kind: &str, name: &str, content: &str) -> Value {
+    json!({
+        "id": id,
+        "type": kind,
+        "name": name,
+        "content": content,
+        "ttl": 1,
+        "proxied": false,
+        "proxiable": true,
+        "locked": false,
+        "zone_id": "zone",
+        "zone_name": "example.com",
+        "created_on": "2022-01-01T00:00:00Z",
+        "modified_on": "2022-01-01T00:00:00Z",
+        "meta": {"auto_added": false}
+    })
+}
@@ -1,15 +1,117 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Zone {
     pub domain: String,
-    private_prefix: String,
-    networks: Vec<Network>,
+    pub private_prefix: String,
+    pub networks: Vec<Network>,
+    #[serde(default, skip_serializing_if = "Soa::is_default")]
+    pub soa: Soa,
+    #[serde(default, skip_serializing_if = "Backends::is_default")]
+    pub backends: Backends,
+    /// Extra public records, named relative to `domain`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<CustomRecord>,
+    /// TTL in seconds of the records in Cloudflare, automatic when left out.
+    /// Networks, servers and aliases inherit it unless they set their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    /// Whether Cloudflare proxies the public records, inherited like `ttl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
 }
 
+/// Which records each backend gets, e.g. only the public ones in Cloudflare
+/// while the private ones are served locally.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backends {
+    #[serde(default = "RecordTypeFilter::both")]
+    pub cloudflare: RecordTypeFilter,
+    #[serde(default = "RecordTypeFilter::both")]
+    pub export: RecordTypeFilter,
+    #[serde(default = "RecordTypeFilter::private_only")]
+    pub serve: RecordTypeFilter,
+    /// Also publish the PTR records to the reverse zones in Cloudflare.
+    #[serde(default)]
+    pub cloudflare_reverse: bool,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Backends {
+            cloudflare: RecordTypeFilter::Both,
+            export: RecordTypeFilter::Both,
+            serve: RecordTypeFilter::Private,
+            cloudflare_reverse: false,
+        }
+    }
+}
+
+impl Backends {
+    fn is_default(&self) -> bool {
+        self == &Backends::default()
+    }
+}
+
+/// Settings for the SOA and NS records of exported zone files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Soa {
+    /// Defaults to `ns1.<domain>`.
+    #[serde(default)]
+    pub nameservers: Vec<String>,
+    /// Defaults to `hostmaster.<domain>`.
+    pub hostmaster: Option<String>,
+    #[serde(default = "Soa::default_ttl")]
+    pub ttl: u32,
+    /// Defaults to the time of the export in seconds since the epoch.
+    pub serial: Option<u32>,
+}
+
+impl Default for Soa {
+    fn default() -> Self {
+        Soa {
+            nameservers: Vec::new(),
+            hostmaster: None,
+            ttl: Soa::default_ttl(),
+            serial: None,
+        }
+    }
+}
+
+impl Soa {
+    fn default_ttl() -> u32 {
+        3600
+    }
+    fn is_default(&self) -> bool {
+        self == &Soa::default()
+    }
+    /// The configured nameservers, or `ns1.<origin>` when there are none.
+    pub fn nameservers(&self, origin: &str) -> Vec<String> {
+        match self.nameservers.as_slice() {
+            [] => vec![format!("ns1.{}", origin)],
+            ns => ns.to_vec(),
+        }
+    }
+    /// The hostmaster as a domain name, `hostmaster.<origin>` by default.
+    pub fn hostmaster(&self, origin: &str) -> String {
+        self.hostmaster
+            .clone()
+            .unwrap_or_else(|| format!("hostmaster.{}", origin))
+            .replacen('@', ".", 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RecordTypeFilter {
     Public,
     Private,
@@ -17,14 +119,20 @@ pub enum RecordTypeFilter {
 }
 
 impl RecordTypeFilter {
-    fn public(&self) -> bool {
+    fn both() -> Self {
+        RecordTypeFilter::Both
+    }
+    fn private_only() -> Self {
+        RecordTypeFilter::Private
+    }
+    pub fn public(&self) -> bool {
         match self {
             RecordTypeFilter::Public => true,
             RecordTypeFilter::Private => false,
             RecordTypeFilter::Both => true,
         }
     }
-    fn private(&self) -> bool {
+    pub fn private(&self) -> bool {
         match self {
             RecordTypeFilter::Public => false,
             RecordTypeFilter::Private => true,
@@ -33,25 +141,96 @@ impl RecordTypeFilter {
     }
 }
 
+/// A config file holding several zones under a top-level `zones` key.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ZoneList {
+    zones: Vec<Zone>,
+}
+
+/// The config files at `path`: the file itself, or every `.yaml` and `.yml`
+/// file in it when it is a directory.
+pub fn config_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        let yaml = file
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+        if yaml && file.is_file() {
+            files.push(file);
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("No config files found in {}", path.display()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a config lists its zones under `zones` instead of being a single zone.
+pub fn lists_zones(source: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(source)
+        .is_ok_and(|v| v.get("zones").is_some_and(|zones| zones.is_sequence()))
+}
+
 impl Zone {
-    pub fn read<P: AsRef<Path>>(path: P) -> Result<Zone> {
-        let p = File::open(path)?;
-        let zone: Zone = serde_yaml::from_reader(p)?;
-        Ok(zone)
+    /// Parses the zones of a config file, which is either a single zone or
+    /// holds a list of them under `zones`.
+    pub fn parse_all(source: &str) -> serde_yaml::Result<Vec<Zone>> {
+        if lists_zones(source) {
+            Ok(serde_yaml::from_str::<ZoneList>(source)?.zones)
+        } else {
+            Ok(vec![serde_yaml::from_str(source)?])
+        }
     }
     pub fn all_records(&self) -> Vec<Record> {
         self.records(RecordTypeFilter::Both)
     }
-    fn records(&self, filter: RecordTypeFilter) -> Vec<Record> {
+    pub fn record_sets(&self, filter: RecordTypeFilter) -> Vec<RecordSet> {
+        RecordSet::group_with(self.records_with_settings(filter))
+    }
+    pub fn settings(&self) -> RecordSettings {
+        RecordSettings::new(self.ttl, self.proxied)
+    }
+    /// The domain all private records live under.
+    pub fn private_origin(&self) -> String {
+        format!("{}.{}", self.private_prefix, self.domain)
+    }
+    /// The domain the private records of `network` live under.
+    pub fn private_domain(&self, network: &Network) -> String {
+        format!("{}.{}.{}", network.name, self.private_prefix, self.domain)
+    }
+    pub fn records(&self, filter: RecordTypeFilter) -> Vec<Record> {
+        self.records_with_settings(filter)
+            .into_iter()
+            .map(|(record, _)| record)
+            .collect()
+    }
+    /// The records along with the settings they inherit from the config.
+    pub fn records_with_settings(&self, filter: RecordTypeFilter) -> Vec<(Record, RecordSettings)> {
+        let settings = self.settings();
         let mut records = Vec::new();
         if filter.public() {
             for network in &self.networks {
-                records.extend(network.public_records(&self.domain));
+                records.extend(network.public_records(&self.domain, settings));
             }
+            records.extend(
+                self.records
+                    .iter()
+                    .map(|r| (r.to_record(&self.domain), settings)),
+            );
         }
         if filter.private() {
             for network in &self.networks {
-                records.extend(network.private_records(&self.private_prefix, &self.domain));
+                records.extend(network.private_records(
+                    &self.private_prefix,
+                    &self.domain,
+                    settings.private(),
+                ));
             }
         }
         records
@@ -59,98 +238,924 @@ impl Zone {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Network {
-    name: String,
-    root: String,
-    servers: Vec<Server>,
+    pub name: String,
+    pub root: String,
+    /// The CIDRs of the network, used to find the reverse zones of its servers.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub subnet: Vec<String>,
+    pub servers: Vec<Server>,
+    /// Extra private records, named relative to `<network>.<private_prefix>.<domain>`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<CustomRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
 }
 
 impl Network {
-    fn public_records(&self, domain: &str) -> Vec<Record> {
-        let mut recs: Vec<Record> = self
+    fn public_records(
+        &self,
+        domain: &str,
+        parent: RecordSettings,
+    ) -> Vec<(Record, RecordSettings)> {
+        let settings = RecordSettings::new(self.ttl, self.proxied).inherit(parent);
+        let mut recs: Vec<(Record, RecordSettings)> = self
             .servers
             .iter()
-            .flat_map(|s| s.public_records(&self.name, &self.root, &domain))
+            .flat_map(|s| s.public_records(&self.name, &self.root, domain, settings))
             .collect();
-        recs.push(Record::Cname(
-            format!("{}.{}", self.name.clone(), domain),
-            format!("{}.{}.{}", self.root, self.name, domain),
+        recs.push((
+            Record::Cname(
+                format!("{}.{}", self.name.clone(), domain),
+                format!("{}.{}.{}", self.root, self.name, domain),
+            ),
+            settings,
         ));
         recs
     }
-    fn private_records(&self, private_prefix: &str, domain: &str) -> Vec<Record> {
-        let mut recs: Vec<Record> = self
+    fn private_records(
+        &self,
+        private_prefix: &str,
+        domain: &str,
+        parent: RecordSettings,
+    ) -> Vec<(Record, RecordSettings)> {
+        let settings = RecordSettings::new(self.ttl, None).inherit(parent);
+        let suffix = format!("{}.{}", &self.name, private_prefix);
+        let mut recs: Vec<(Record, RecordSettings)> = self
             .servers
             .iter()
-            .flat_map(|s| s.private_records(&format!("{}.{}", &self.name, private_prefix), &domain))
+            .flat_map(|s| s.private_records(&suffix, domain, settings))
             .collect();
-        recs.push(Record::Cname(
-            format!("{}.{}.{}", self.name, private_prefix, domain),
-            format!("{}.{}.{}.{}", self.root, self.name, private_prefix, domain),
+        let base = format!("{}.{}.{}", self.name, private_prefix, domain);
+        recs.push((
+            Record::Cname(
+                base.clone(),
+                format!("{}.{}.{}.{}", self.root, self.name, private_prefix, domain),
+            ),
+            settings,
         ));
+        recs.extend(self.records.iter().map(|r| (r.to_record(&base), settings)));
         recs
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Server {
-    name: String,
-    private_ip: String,
-    #[serde(default)]
-    alias: Vec<String>,
+    pub name: String,
+    /// Allocated from the network's subnet when left out, see `ipam`.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub private_ip: Vec<String>,
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub private_ipv6: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alias: Vec<Alias>,
+    /// Extra private records, named relative to the server's private name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<CustomRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
 }
 
 impl Server {
-    fn public_records(&self, suffix: &str, root: &str, domain: &str) -> Vec<Record> {
+    fn public_records(
+        &self,
+        suffix: &str,
+        root: &str,
+        domain: &str,
+        parent: RecordSettings,
+    ) -> Vec<(Record, RecordSettings)> {
+        let settings = RecordSettings::new(self.ttl, self.proxied).inherit(parent);
         let mut v = Vec::new();
         let root_full = format!("{}.{}.{}", root, suffix, domain);
         if self.name != root {
-            v.push(Record::Cname(
-                format!("{}.{}.{}", &self.name, suffix, domain),
-                root_full.to_string(),
+            v.push((
+                Record::Cname(
+                    format!("{}.{}.{}", &self.name, suffix, domain),
+                    root_full.to_string(),
+                ),
+                settings,
             ));
         }
         v.extend(self.alias.iter().map(|a| {
-            Record::Cname(
-                format!("{}.{}.{}", a, suffix, domain),
-                format!("{}.{}.{}", root, suffix, domain),
+            (
+                Record::Cname(
+                    format!("{}.{}.{}", a.name, suffix, domain),
+                    format!("{}.{}.{}", root, suffix, domain),
+                ),
+                a.settings().inherit(settings),
             )
         }));
         v
     }
-    fn private_records(&self, suffix: &str, domain: &str) -> Vec<Record> {
-        let mut v = Vec::new();
-        v.push(Record::A(
-            format!("{}.{}.{}", self.name, suffix, domain),
-            self.private_ip.clone(),
-        ));
+    fn private_records(
+        &self,
+        suffix: &str,
+        domain: &str,
+        parent: RecordSettings,
+    ) -> Vec<(Record, RecordSettings)> {
+        let settings = RecordSettings::new(self.ttl, None).inherit(parent);
+        let name = format!("{}.{}.{}", self.name, suffix, domain);
+        let mut v: Vec<(Record, RecordSettings)> = self
+            .private_ip
+            .iter()
+            .map(|ip| (Record::A(name.clone(), ip.clone()), settings))
+            .collect();
+        // Cloudflare reports IPv6 addresses in their compressed form, so use it too.
+        v.extend(self.private_ipv6.iter().map(|ip| {
+            let ip = ip.parse::<Ipv6Addr>().map_or(ip.clone(), |a| a.to_string());
+            (Record::Aaaa(name.clone(), ip), settings)
+        }));
         v.extend(self.alias.iter().map(|a| {
-            Record::Cname(
-                format!("{}.{}.{}", a, suffix, domain),
-                format!("{}.{}.{}", self.name, suffix, domain),
+            (
+                Record::Cname(
+                    format!("{}.{}.{}", a.name, suffix, domain),
+                    format!("{}.{}.{}", self.name, suffix, domain),
+                ),
+                a.settings().private().inherit(settings),
             )
         }));
+        v.extend(self.records.iter().map(|r| (r.to_record(&name), settings)));
         v
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+/// Another name for a server, written as just the name or as a map when it
+/// has its own `ttl` or `proxied` setting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "AliasDoc", into = "AliasDoc")]
+pub struct Alias {
+    pub name: String,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+}
+
+impl Alias {
+    pub fn settings(&self) -> RecordSettings {
+        RecordSettings::new(self.ttl, self.proxied)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum AliasDoc {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proxied: Option<bool>,
+    },
+}
+
+impl From<AliasDoc> for Alias {
+    fn from(doc: AliasDoc) -> Self {
+        match doc {
+            AliasDoc::Name(name) => Alias {
+                name,
+                ttl: None,
+                proxied: None,
+            },
+            AliasDoc::Full { name, ttl, proxied } => Alias { name, ttl, proxied },
+        }
+    }
+}
+
+impl From<Alias> for AliasDoc {
+    fn from(alias: Alias) -> Self {
+        match alias {
+            Alias {
+                name,
+                ttl: None,
+                proxied: None,
+            } => AliasDoc::Name(name),
+            Alias { name, ttl, proxied } => AliasDoc::Full { name, ttl, proxied },
+        }
+    }
+}
+
+/// The TTL and Cloudflare proxy status of a record. A setting left out is
+/// inherited from the level above.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct RecordSettings {
+    /// `None` leaves the TTL to Cloudflare.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+}
+
+impl RecordSettings {
+    pub fn new(ttl: Option<u32>, proxied: Option<bool>) -> Self {
+        RecordSettings { ttl, proxied }
+    }
+    fn inherit(self, parent: RecordSettings) -> Self {
+        RecordSettings {
+            ttl: self.ttl.or(parent.ttl),
+            proxied: self.proxied.or(parent.proxied),
+        }
+    }
+    /// Private addresses cannot be proxied, so private records only keep the TTL.
+    fn private(self) -> Self {
+        RecordSettings {
+            ttl: self.ttl,
+            proxied: None,
+        }
+    }
+    /// The settings Cloudflare ends up with for a record of type `kind`. Only
+    /// addresses and CNAMEs can be proxied, and proxied records always have an
+    /// automatic TTL.
+    pub fn resolve(self, kind: &str) -> Self {
+        let proxied = match kind {
+            "A" | "AAAA" | "CNAME" => Some(self.proxied.unwrap_or(false)),
+            _ => None,
+        };
+        RecordSettings {
+            ttl: if proxied == Some(true) {
+                None
+            } else {
+                self.ttl
+            },
+            proxied,
+        }
+    }
+}
+
+impl fmt::Display for RecordSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ttl) = self.ttl {
+            parts.push(format!("ttl {}", ttl));
+        }
+        if self.proxied == Some(true) {
+            parts.push("proxied".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A record written out in the config. `name` is relative to the level the
+/// record is defined at, `@` (the default) being that level's own name.
+/// Targets and exchanges are full domain names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "UPPERCASE")]
+pub enum CustomRecord {
+    Txt {
+        #[serde(
+            default = "CustomRecord::at",
+            skip_serializing_if = "CustomRecord::is_at"
+        )]
+        name: String,
+        value: String,
+    },
+    Mx {
+        #[serde(
+            default = "CustomRecord::at",
+            skip_serializing_if = "CustomRecord::is_at"
+        )]
+        name: String,
+        priority: u16,
+        value: String,
+    },
+    Srv {
+        name: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Caa {
+        #[serde(
+            default = "CustomRecord::at",
+            skip_serializing_if = "CustomRecord::is_at"
+        )]
+        name: String,
+        #[serde(default)]
+        flags: u8,
+        tag: String,
+        value: String,
+    },
+}
+
+impl CustomRecord {
+    fn at() -> String {
+        "@".to_string()
+    }
+    fn is_at(name: &str) -> bool {
+        name == "@"
+    }
+    pub fn name(&self) -> &str {
+        match self {
+            CustomRecord::Txt { name, .. }
+            | CustomRecord::Mx { name, .. }
+            | CustomRecord::Srv { name, .. }
+            | CustomRecord::Caa { name, .. } => name,
+        }
+    }
+    pub fn to_record(&self, base: &str) -> Record {
+        let name = match self.name() {
+            "@" => base.to_string(),
+            name => format!("{}.{}", name, base),
+        };
+        let absolute = |target: &str| target.trim_end_matches('.').to_string();
+        match self {
+            CustomRecord::Txt { value, .. } => Record::Txt(name, value.clone()),
+            CustomRecord::Mx {
+                priority, value, ..
+            } => Record::Mx(name, *priority, absolute(value)),
+            CustomRecord::Srv {
+                priority,
+                weight,
+                port,
+                target,
+                ..
+            } => Record::Srv(name, *priority, *weight, *port, absolute(target)),
+            CustomRecord::Caa {
+                flags, tag, value, ..
+            } => Record::Caa(name, *flags, tag.clone(), value.clone()),
+        }
+    }
+
+    /// The inverse of `to_record`, for records at or below `base` of a type
+    /// the config can hold.
+    pub fn from_record(record: &Record, base: &str) -> Option<CustomRecord> {
+        let full = record.name();
+        let name = match full.strip_suffix(&format!(".{}", base)) {
+            _ if full == base => CustomRecord::at(),
+            Some(name) => name.to_string(),
+            None => return None,
+        };
+        match record.clone() {
+            Record::Txt(_, value) => Some(CustomRecord::Txt { name, value }),
+            Record::Mx(_, priority, value) => Some(CustomRecord::Mx {
+                name,
+                priority,
+                value,
+            }),
+            Record::Srv(_, priority, weight, port, target) => Some(CustomRecord::Srv {
+                name,
+                priority,
+                weight,
+                port,
+                target,
+            }),
+            Record::Caa(_, flags, tag, value) => Some(CustomRecord::Caa {
+                name,
+                flags,
+                tag,
+                value,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Accepts either a single string or a list of strings, writing single
+/// element lists back as a plain string.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    pub fn serialize<S: Serializer>(v: &[String], s: S) -> Result<S::Ok, S::Error> {
+        match v {
+            [one] => one.serialize(s),
+            many => many.serialize(s),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+        Ok(match OneOrMany::deserialize(d)? {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[serde(into = "RecordDoc", try_from = "RecordDoc")]
 pub enum Record {
     A(String, String),
+    Aaaa(String, String),
     Cname(String, String),
+    Txt(String, String),
+    Ptr(String, String),
+    /// Priority and mail exchanger.
+    Mx(String, u16, String),
+    /// Priority, weight, port and target.
+    Srv(String, u16, u16, u16, String),
+    /// Flags, tag and value.
+    Caa(String, u8, String, String),
 }
 
 impl Record {
+    /// Builds a record from its type and value in presentation format,
+    /// e.g. `10 mail.example.com` for an MX record.
+    pub fn new(kind: &str, name: String, value: String) -> Result<Record> {
+        let invalid = || anyhow!("Invalid {} record {} for {}", kind, value, name);
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        match kind {
+            "A" => Ok(Record::A(name, value)),
+            "AAAA" => Ok(Record::Aaaa(name, value)),
+            "CNAME" => Ok(Record::Cname(name, value)),
+            "TXT" => Ok(Record::Txt(name, value)),
+            "PTR" => Ok(Record::Ptr(name, value)),
+            "MX" => match fields[..] {
+                [priority, exchange] => Ok(Record::Mx(
+                    name.clone(),
+                    priority.parse().map_err(|_| invalid())?,
+                    exchange.to_string(),
+                )),
+                _ => Err(invalid()),
+            },
+            "SRV" => match fields[..] {
+                [priority, weight, port, target] => Ok(Record::Srv(
+                    name.clone(),
+                    priority.parse().map_err(|_| invalid())?,
+                    weight.parse().map_err(|_| invalid())?,
+                    port.parse().map_err(|_| invalid())?,
+                    target.to_string(),
+                )),
+                _ => Err(invalid()),
+            },
+            "CAA" => {
+                let mut parts = value.splitn(3, ' ');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(flags), Some(tag), Some(v)) => Ok(Record::Caa(
+                        name.clone(),
+                        flags.parse().map_err(|_| invalid())?,
+                        tag.to_string(),
+                        v.trim_matches('"').to_string(),
+                    )),
+                    _ => Err(invalid()),
+                }
+            }
+            other => Err(anyhow!("Unsupported record type {}", other)),
+        }
+    }
     pub fn name(&self) -> String {
         match self {
             Record::A(name, _) => name.clone(),
+            Record::Aaaa(name, _) => name.clone(),
             Record::Cname(name, _) => name.clone(),
+            Record::Txt(name, _) => name.clone(),
+            Record::Ptr(name, _) => name.clone(),
+            Record::Mx(name, ..) => name.clone(),
+            Record::Srv(name, ..) => name.clone(),
+            Record::Caa(name, ..) => name.clone(),
         }
     }
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Record::A(..) => "A",
+            Record::Aaaa(..) => "AAAA",
+            Record::Cname(..) => "CNAME",
+            Record::Txt(..) => "TXT",
+            Record::Ptr(..) => "PTR",
+            Record::Mx(..) => "MX",
+            Record::Srv(..) => "SRV",
+            Record::Caa(..) => "CAA",
+        }
+    }
+    /// The value in presentation format, as accepted by `Record::new`.
     pub fn value(&self) -> String {
         match self {
             Record::A(_, value) => value.clone(),
+            Record::Aaaa(_, value) => value.clone(),
             Record::Cname(_, value) => value.clone(),
+            Record::Txt(_, value) => value.clone(),
+            Record::Ptr(_, value) => value.clone(),
+            Record::Mx(_, priority, exchange) => format!("{} {}", priority, exchange),
+            Record::Srv(_, priority, weight, port, target) => {
+                format!("{} {} {} {}", priority, weight, port, target)
+            }
+            Record::Caa(_, flags, tag, value) => format!("{} {} \"{}\"", flags, tag, value),
+        }
+    }
+}
+
+/// Flat `{type, name, value}` representation used when records are written to plan files.
+#[derive(Serialize, Deserialize)]
+struct RecordDoc {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    value: String,
+}
+
+impl From<Record> for RecordDoc {
+    fn from(r: Record) -> Self {
+        RecordDoc {
+            kind: r.kind().to_string(),
+            name: r.name(),
+            value: r.value(),
+        }
+    }
+}
+
+impl TryFrom<RecordDoc> for Record {
+    type Error = anyhow::Error;
+    fn try_from(doc: RecordDoc) -> Result<Self> {
+        Record::new(&doc.kind, doc.name, doc.value)
+    }
+}
+
+/// All values of one type published under one name, e.g. several A records
+/// for a round-robin host. The values share their settings.
+#[derive(Debug, Eq, PartialEq, Clone, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RecordSet {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub values: BTreeSet<String>,
+    #[serde(default)]
+    pub settings: RecordSettings,
+}
+
+impl RecordSet {
+    /// Groups records by name and type, ordered by name.
+    #[cfg(test)]
+    pub fn group(records: Vec<Record>) -> Vec<RecordSet> {
+        RecordSet::group_with(
+            records
+                .into_iter()
+                .map(|r| (r, RecordSettings::default()))
+                .collect(),
+        )
+    }
+
+    /// Groups records like `group`, each set taking the resolved settings of
+    /// its first record.
+    pub fn group_with(records: Vec<(Record, RecordSettings)>) -> Vec<RecordSet> {
+        let mut sets: BTreeMap<(String, &'static str), (BTreeSet<String>, RecordSettings)> =
+            BTreeMap::new();
+        for (r, settings) in records {
+            let kind = r.kind();
+            sets.entry((r.name(), kind))
+                .or_insert_with(|| (BTreeSet::new(), settings.resolve(kind)))
+                .0
+                .insert(r.value());
+        }
+        sets.into_iter()
+            .map(|((name, kind), (values, settings))| RecordSet {
+                name,
+                kind: kind.to_string(),
+                values,
+                settings,
+            })
+            .collect()
+    }
+
+    pub fn record(&self, value: &str) -> Result<Record> {
+        Record::new(&self.kind, self.name.clone(), value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(config: &str) -> Vec<(String, &'static str, String)> {
+        let zone: Zone = serde_yaml::from_str(config).unwrap();
+        zone.all_records()
+            .into_iter()
+            .map(|r| (r.name(), r.kind(), r.value()))
+            .collect()
+    }
+
+    fn rec(name: &str, kind: &'static str, value: &str) -> (String, &'static str, String) {
+        (name.to_string(), kind, value.to_string())
+    }
+
+    #[test]
+    fn all_records_for_a_single_network() {
+        let records = records(
+            "
+domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00:0::1
+      - name: nas
+        private_ip: [10.0.0.2, 10.0.0.3]
+        alias: [files]
+",
+        );
+        assert_eq!(
+            records,
+            vec![
+                rec("nas.office.example.com", "CNAME", "gw.office.example.com"),
+                rec("files.office.example.com", "CNAME", "gw.office.example.com"),
+                rec("office.example.com", "CNAME", "gw.office.example.com"),
+                rec("gw.office.int.example.com", "A", "10.0.0.1"),
+                rec("gw.office.int.example.com", "AAAA", "fd00::1"),
+                rec("nas.office.int.example.com", "A", "10.0.0.2"),
+                rec("nas.office.int.example.com", "A", "10.0.0.3"),
+                rec(
+                    "files.office.int.example.com",
+                    "CNAME",
+                    "nas.office.int.example.com"
+                ),
+                rec(
+                    "office.int.example.com",
+                    "CNAME",
+                    "gw.office.int.example.com"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_records_for_several_networks() {
+        let records = records(
+            "
+domain: example.com
+private_prefix: vpn
+networks:
+  - name: home
+    root: router
+    servers:
+      - name: router
+        private_ip: 192.168.1.1
+  - name: lab
+    root: lab1
+    servers:
+      - name: lab1
+        private_ip: 10.1.0.1
+        alias: [ci]
+",
+        );
+        assert_eq!(
+            records,
+            vec![
+                rec("home.example.com", "CNAME", "router.home.example.com"),
+                rec("ci.lab.example.com", "CNAME", "lab1.lab.example.com"),
+                rec("lab.example.com", "CNAME", "lab1.lab.example.com"),
+                rec("router.home.vpn.example.com", "A", "192.168.1.1"),
+                rec(
+                    "home.vpn.example.com",
+                    "CNAME",
+                    "router.home.vpn.example.com"
+                ),
+                rec("lab1.lab.vpn.example.com", "A", "10.1.0.1"),
+                rec(
+                    "ci.lab.vpn.example.com",
+                    "CNAME",
+                    "lab1.lab.vpn.example.com"
+                ),
+                rec("lab.vpn.example.com", "CNAME", "lab1.lab.vpn.example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn configs_hold_one_zone_or_a_list() {
+        let zone = "domain: example.com\nprivate_prefix: int\nnetworks: []\n";
+        assert_eq!(Zone::parse_all(zone).unwrap().len(), 1);
+        assert!(!lists_zones(zone));
+
+        let list = "
+zones:
+  - domain: example.com
+    private_prefix: int
+    networks: []
+  - domain: staging.example.com
+    private_prefix: int
+    networks: []
+";
+        assert!(lists_zones(list));
+        let domains: Vec<String> = Zone::parse_all(list)
+            .unwrap()
+            .into_iter()
+            .map(|z| z.domain)
+            .collect();
+        assert_eq!(domains, vec!["example.com", "staging.example.com"]);
+    }
+
+    #[test]
+    fn config_directories_hold_yaml_files() {
+        let dir = std::env::temp_dir().join("netmgr-config-dir");
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["prod.yaml", "lab.yml", "prod.lock", "notes.txt"] {
+            fs::write(dir.join(file), "").unwrap();
         }
+        let files = config_files(&dir).unwrap();
+        assert_eq!(files, vec![dir.join("lab.yml"), dir.join("prod.yaml")]);
+        assert_eq!(config_files(&files[0]).unwrap(), vec![files[0].clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backends_choose_their_records() {
+        let zone: Zone = serde_yaml::from_str(
+            "
+domain: example.com
+private_prefix: int
+backends:
+  cloudflare: public
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+",
+        )
+        .unwrap();
+        assert_eq!(
+            zone.backends,
+            Backends {
+                cloudflare: RecordTypeFilter::Public,
+                export: RecordTypeFilter::Both,
+                serve: RecordTypeFilter::Private,
+                cloudflare_reverse: false,
+            }
+        );
+        let names =
+            |filter| -> Vec<String> { zone.records(filter).iter().map(|r| r.name()).collect() };
+        assert_eq!(names(zone.backends.cloudflare), vec!["office.example.com"]);
+        assert_eq!(
+            names(zone.backends.serve),
+            vec!["gw.office.int.example.com", "office.int.example.com"]
+        );
+    }
+
+    #[test]
+    fn custom_records_at_every_level() {
+        let records = records(
+            "
+domain: example.com
+private_prefix: int
+records:
+  - type: TXT
+    value: v=spf1 -all
+  - type: CAA
+    tag: issue
+    value: letsencrypt.org
+networks:
+  - name: office
+    root: gw
+    records:
+      - type: SRV
+        name: _ldap._tcp
+        priority: 10
+        weight: 5
+        port: 389
+        target: gw.office.int.example.com.
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        records:
+          - type: MX
+            priority: 10
+            value: gw.office.int.example.com
+",
+        );
+        let custom: Vec<_> = records
+            .into_iter()
+            .filter(|(_, kind, _)| !["A", "CNAME"].contains(kind))
+            .collect();
+        assert_eq!(
+            custom,
+            vec![
+                rec("example.com", "TXT", "v=spf1 -all"),
+                rec("example.com", "CAA", "0 issue \"letsencrypt.org\""),
+                rec(
+                    "gw.office.int.example.com",
+                    "MX",
+                    "10 gw.office.int.example.com"
+                ),
+                rec(
+                    "_ldap._tcp.office.int.example.com",
+                    "SRV",
+                    "10 5 389 gw.office.int.example.com"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_are_inherited() {
+        let zone: Zone = serde_yaml::from_str(
+            "
+domain: example.com
+private_prefix: int
+ttl: 300
+networks:
+  - name: office
+    root: gw
+    proxied: true
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+      - name: nas
+        private_ip: 10.0.0.2
+        ttl: 60
+        alias:
+          - files
+          - name: www
+            proxied: false
+",
+        )
+        .unwrap();
+        let settings: Vec<(String, String)> = zone
+            .record_sets(RecordTypeFilter::Both)
+            .into_iter()
+            .map(|s| (s.name, s.settings.to_string()))
+            .collect();
+        assert_eq!(
+            settings,
+            vec![
+                (
+                    "files.office.example.com".to_string(),
+                    "proxied".to_string()
+                ),
+                (
+                    "files.office.int.example.com".to_string(),
+                    "ttl 60".to_string()
+                ),
+                (
+                    "gw.office.int.example.com".to_string(),
+                    "ttl 300".to_string()
+                ),
+                ("nas.office.example.com".to_string(), "proxied".to_string()),
+                (
+                    "nas.office.int.example.com".to_string(),
+                    "ttl 60".to_string()
+                ),
+                ("office.example.com".to_string(), "proxied".to_string()),
+                ("office.int.example.com".to_string(), "ttl 300".to_string()),
+                ("www.office.example.com".to_string(), "ttl 60".to_string()),
+                (
+                    "www.office.int.example.com".to_string(),
+                    "ttl 60".to_string()
+                ),
+            ]
+        );
+
+        let yaml = serde_yaml::to_string(&zone.networks[0].servers[1].alias).unwrap();
+        assert_eq!(yaml, "- files\n- name: www\n  proxied: false\n");
+    }
+
+    #[test]
+    fn typed_records_round_trip_through_their_value() {
+        let records = vec![
+            Record::Mx(
+                "example.com".to_string(),
+                10,
+                "mail.example.com".to_string(),
+            ),
+            Record::Srv(
+                "_sip._udp.example.com".to_string(),
+                1,
+                2,
+                5060,
+                "sip.example.com".to_string(),
+            ),
+            Record::Caa(
+                "example.com".to_string(),
+                128,
+                "iodef".to_string(),
+                "mailto:ca@example.com".to_string(),
+            ),
+        ];
+        for record in records {
+            let parsed = Record::new(record.kind(), record.name(), record.value()).unwrap();
+            assert_eq!(parsed, record);
+        }
+        assert!(Record::new("MX", "example.com".to_string(), "mail".to_string()).is_err());
+    }
+
+    #[test]
+    fn record_sets_group_values_by_name_and_type() {
+        let sets = RecordSet::group(vec![
+            Record::A("a.example.com".to_string(), "10.0.0.2".to_string()),
+            Record::Aaaa("a.example.com".to_string(), "fd00::1".to_string()),
+            Record::A("a.example.com".to_string(), "10.0.0.1".to_string()),
+        ]);
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].kind, "A");
+        assert_eq!(
+            sets[0].values.iter().collect::<Vec<_>>(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+        assert_eq!(sets[1].kind, "AAAA");
     }
 }
@@ -0,0 +1,410 @@
+use crate::model::RecordSet;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct Diff {
+    pub superflous: Vec<RecordSet>,
+    pub missing: Vec<RecordSet>,
+    pub changed: Vec<(RecordSet, RecordSet)>,
+}
+
+impl Diff {
+    pub fn new(a: Vec<RecordSet>, b: Vec<RecordSet>) -> Self {
+        let mut superflous = Vec::new();
+        let mut missing = Vec::new();
+        let mut changed = Vec::new();
+
+        let a: HashMap<(String, String), RecordSet> = a
+            .into_iter()
+            .map(|r| ((r.name.clone(), r.kind.clone()), r))
+            .collect();
+        let b: HashMap<(String, String), RecordSet> = b
+            .into_iter()
+            .map(|r| ((r.name.clone(), r.kind.clone()), r))
+            .collect();
+
+        for (key, set) in a.iter() {
+            match b.get(key) {
+                None => superflous.push(set.clone()),
+                Some(desired) if desired != set => changed.push((desired.clone(), set.clone())),
+                Some(_) => {}
+            }
+        }
+        for (key, set) in b.iter() {
+            if !a.contains_key(key) {
+                missing.push(set.clone());
+            }
+        }
+        Diff {
+            superflous,
+            missing,
+            changed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.superflous.is_empty() && self.missing.is_empty() && self.changed.is_empty()
+    }
+
+    /// The number of record sets that differ.
+    pub fn len(&self) -> usize {
+        self.superflous.len() + self.missing.len() + self.changed.len()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut changes: Vec<Change> = self
+            .changed
+            .iter()
+            .map(|(new, old)| Change::Update {
+                old: old.clone(),
+                new: new.clone(),
+            })
+            .chain(
+                self.missing
+                    .iter()
+                    .map(|r| Change::Create { set: r.clone() }),
+            )
+            .chain(
+                self.superflous
+                    .iter()
+                    .map(|r| Change::Delete { set: r.clone() }),
+            )
+            .collect();
+        changes.sort_by_key(|c| c.set().clone());
+        for change in changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Change {
+    Create { set: RecordSet },
+    Update { old: RecordSet, new: RecordSet },
+    Delete { set: RecordSet },
+}
+
+/// The live state a plan was computed against.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub records: Vec<RecordSet>,
+    pub owned: Vec<(String, String)>,
+}
+
+impl Baseline {
+    pub fn new(mut records: Vec<RecordSet>, owned: &HashMap<(String, String), String>) -> Self {
+        records.sort();
+        let mut owned: Vec<(String, String)> = owned.keys().cloned().collect();
+        owned.sort();
+        Baseline { records, owned }
+    }
+}
+
+/// The set of changes needed to bring the live zone in line with the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Plan {
+    pub domain: String,
+    pub changes: Vec<Change>,
+    /// Superfluous or differing records left alone because netmgr does not own them.
+    pub ignored: Vec<RecordSet>,
+    pub baseline: Baseline,
+}
+
+impl Plan {
+    pub fn new(
+        domain: &str,
+        current: Vec<RecordSet>,
+        desired: Vec<RecordSet>,
+        owned: &HashMap<(String, String), String>,
+    ) -> Self {
+        let mut plan = Plan {
+            domain: domain.to_string(),
+            baseline: Baseline::new(current.clone(), owned),
+            ..Default::default()
+        };
+        let d = Diff::new(current, desired);
+        // Records netmgr does not own are never overwritten, only reported.
+        for (new, old) in d.changed {
+            if owned.contains_key(&(new.name.clone(), new.kind.clone())) {
+                plan.changes.push(Change::Update { old, new });
+            } else {
+                plan.ignored.push(old);
+            }
+        }
+        for set in d.missing {
+            plan.changes.push(Change::Create { set });
+        }
+        for set in d.superflous {
+            if owned.contains_key(&(set.name.clone(), set.kind.clone())) {
+                plan.changes.push(Change::Delete { set });
+            } else {
+                plan.ignored.push(set);
+            }
+        }
+        plan.changes.sort_by_key(|c| c.set().clone());
+        plan.ignored.sort();
+        plan
+    }
+
+    /// Reads the plans saved with `write_all`, or a single saved plan.
+    pub fn read_all<P: AsRef<Path>>(path: P) -> Result<Vec<Plan>> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Saved {
+            One(Plan),
+            Many(Vec<Plan>),
+        }
+        let f = File::open(&path)?;
+        let plans = match serde_json::from_reader(f)? {
+            Saved::One(plan) => vec![plan],
+            Saved::Many(plans) => plans,
+        };
+        if plans.is_empty() {
+            return Err(anyhow!("{} contains no plans", path.as_ref().display()));
+        }
+        Ok(plans)
+    }
+
+    pub fn write_all<P: AsRef<Path>>(plans: &[&Plan], path: P) -> Result<()> {
+        let f = File::create(path)?;
+        serde_json::to_writer_pretty(f, plans)?;
+        Ok(())
+    }
+
+    /// Fails if the live state no longer matches the state this plan was computed against.
+    pub fn check_drift(&self, live: &Baseline) -> Result<()> {
+        if &self.baseline != live {
+            return Err(anyhow!(
+                "The records in {} have changed since this plan was created, run `netmgr plan` again",
+                self.domain
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The number of record sets to create, update and delete.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let count = |f: fn(&Change) -> bool| self.changes.iter().filter(|c| f(c)).count();
+        (
+            count(|c| matches!(c, Change::Create { .. })),
+            count(|c| matches!(c, Change::Update { .. })),
+            count(|c| matches!(c, Change::Delete { .. })),
+        )
+    }
+
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "no changes".to_string();
+        }
+        let (create, update, delete) = self.counts();
+        format!(
+            "{} to create, {} to update, {} to delete",
+            create, update, delete
+        )
+    }
+
+    /// Like `summary`, for once the plan has been applied.
+    pub fn applied_summary(&self) -> String {
+        if self.is_empty() {
+            return "no changes".to_string();
+        }
+        let (create, update, delete) = self.counts();
+        format!("{} created, {} updated, {} deleted", create, update, delete)
+    }
+}
+
+impl Change {
+    pub fn set(&self) -> &RecordSet {
+        match self {
+            Change::Create { set } => set,
+            Change::Update { new, .. } => new,
+            Change::Delete { set } => set,
+        }
+    }
+}
+
+fn values(set: &RecordSet) -> String {
+    let values = set
+        .values
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    match set.settings.to_string() {
+        settings if settings.is_empty() => values,
+        settings => format!("{} ({})", values, settings),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Create { set } => write!(f, "  + {} {} {}", set.name, set.kind, values(set)),
+            Change::Update { old, new } => write!(
+                f,
+                "  ~ {} {} {} -> {}",
+                new.name,
+                new.kind,
+                values(old),
+                values(new)
+            ),
+            Change::Delete { set } => write!(f, "  - {} {} {}", set.name, set.kind, values(set)),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for set in &self.ignored {
+            writeln!(
+                f,
+                "Ignoring {} {}: not managed by netmgr",
+                set.name, set.kind
+            )?;
+        }
+        if self.is_empty() {
+            return writeln!(f, "No changes. DNS records are up-to-date.");
+        }
+        writeln!(f, "netmgr will perform the following actions:")?;
+        writeln!(f)?;
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Plan: {}.", self.summary())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Record;
+
+    fn a(name: &str, ip: &str) -> Record {
+        Record::A(name.to_string(), ip.to_string())
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        Record::Cname(name.to_string(), target.to_string())
+    }
+
+    #[test]
+    fn diff_finds_missing_records() {
+        let desired = RecordSet::group(vec![a("new.example.com", "10.0.0.1")]);
+        let d = Diff::new(Vec::new(), desired.clone());
+        assert_eq!(d.missing, desired);
+        assert!(d.superflous.is_empty() && d.changed.is_empty());
+    }
+
+    #[test]
+    fn diff_finds_superflous_records() {
+        let current = RecordSet::group(vec![cname("old.example.com", "gw.example.com")]);
+        let d = Diff::new(current.clone(), Vec::new());
+        assert_eq!(d.superflous, current);
+        assert!(d.missing.is_empty() && d.changed.is_empty());
+    }
+
+    #[test]
+    fn diff_finds_changed_records() {
+        let current = RecordSet::group(vec![cname("www.example.com", "a.example.com")]);
+        let desired = RecordSet::group(vec![cname("www.example.com", "b.example.com")]);
+        let d = Diff::new(current.clone(), desired.clone());
+        assert_eq!(d.changed, vec![(desired[0].clone(), current[0].clone())]);
+        assert!(d.missing.is_empty() && d.superflous.is_empty());
+    }
+
+    #[test]
+    fn diff_compares_all_values_of_a_name() {
+        let current = RecordSet::group(vec![a("rr.example.com", "10.0.0.1")]);
+        let desired = RecordSet::group(vec![
+            a("rr.example.com", "10.0.0.1"),
+            a("rr.example.com", "10.0.0.2"),
+        ]);
+
+        let d = Diff::new(current.clone(), desired.clone());
+
+        assert!(d.missing.is_empty() && d.superflous.is_empty());
+        assert_eq!(d.changed, vec![(desired[0].clone(), current[0].clone())]);
+        assert!(Diff::new(desired.clone(), desired).is_empty());
+    }
+
+    #[test]
+    fn plan_only_deletes_owned_records() {
+        let current = vec![
+            cname("owned.example.com", "gw.example.com"),
+            cname("manual.example.com", "gw.example.com"),
+            Record::Txt("owned.example.com".to_string(), "manual".to_string()),
+        ];
+        let owned = HashMap::from([(
+            ("owned.example.com".to_string(), "CNAME".to_string()),
+            "marker".to_string(),
+        )]);
+
+        let plan = Plan::new("example.com", RecordSet::group(current), Vec::new(), &owned);
+
+        assert_eq!(plan.changes.len(), 1);
+        assert_eq!(plan.changes[0].set().name, "owned.example.com");
+        assert_eq!(plan.changes[0].set().kind, "CNAME");
+        let ignored: Vec<(&str, &str)> = plan
+            .ignored
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind.as_str()))
+            .collect();
+        assert_eq!(
+            ignored,
+            vec![
+                ("manual.example.com", "CNAME"),
+                ("owned.example.com", "TXT")
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_survive_a_round_trip_through_json() {
+        let plan = Plan::new(
+            "example.com",
+            RecordSet::group(vec![a("a.example.com", "10.0.0.1")]),
+            RecordSet::group(vec![a("a.example.com", "10.0.0.2")]),
+            &HashMap::from([(
+                ("a.example.com".to_string(), "A".to_string()),
+                "marker".to_string(),
+            )]),
+        );
+        let json = serde_json::to_string(&plan).unwrap();
+        let read: Plan = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.changes.len(), 1);
+        assert!(read.check_drift(&plan.baseline).is_ok());
+        assert!(read.check_drift(&Baseline::default()).is_err());
+    }
+
+    #[test]
+    fn saved_plans_hold_every_zone() {
+        let forward = Plan::new("example.com", vec![], vec![], &HashMap::new());
+        let reverse = Plan::new("0.10.in-addr.arpa", vec![], vec![], &HashMap::new());
+        let path = std::env::temp_dir().join("netmgr-saved-plans.json");
+        Plan::write_all(&[&forward, &reverse], &path).unwrap();
+        let domains: Vec<String> = Plan::read_all(&path)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.domain)
+            .collect();
+        assert_eq!(domains, vec!["example.com", "0.10.in-addr.arpa"]);
+
+        std::fs::write(&path, serde_json::to_string(&forward).unwrap()).unwrap();
+        assert_eq!(Plan::read_all(&path).unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+//! DNS record endpoints keeping the record content as a plain string. The
+//! `cloudflare` crate only models a few record types and fails to list a
+//! zone holding any other, like the PTR records of a reverse zone.
+use crate::model::{Record, RecordSettings};
+use ::cloudflare::framework::endpoint::{Endpoint, Method};
+use ::cloudflare::framework::response::ApiResult;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Deserialize, Debug)]
+pub struct DnsRecord {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub content: String,
+    pub priority: Option<u16>,
+    /// The fields of SRV and CAA records.
+    pub data: Option<Value>,
+    pub ttl: Option<u32>,
+    pub proxied: Option<bool>,
+}
+
+impl ApiResult for DnsRecord {}
+
+/// The TTL Cloudflare uses to mean automatic.
+const AUTOMATIC_TTL: u32 = 1;
+
+impl DnsRecord {
+    pub fn settings(&self) -> RecordSettings {
+        RecordSettings::new(self.ttl.filter(|&ttl| ttl != AUTOMATIC_TTL), self.proxied)
+    }
+
+    pub fn to_record(&self) -> Option<Record> {
+        let name = self.name.clone();
+        let data = self.data.as_ref();
+        let field = |key: &str| data.and_then(|d| d.get(key));
+        let number = |key: &str| field(key).and_then(Value::as_u64);
+        let text = |key: &str| field(key).and_then(Value::as_str).map(str::to_string);
+        match self.kind.as_str() {
+            "MX" => Some(Record::Mx(name, self.priority?, self.content.clone())),
+            "SRV" if data.is_some() => Some(Record::Srv(
+                name,
+                number("priority")? as u16,
+                number("weight")? as u16,
+                number("port")? as u16,
+                text("target")?,
+            )),
+            "CAA" if data.is_some() => Some(Record::Caa(
+                name,
+                number("flags")? as u8,
+                text("tag")?,
+                text("value")?,
+            )),
+            kind => Record::new(kind, name, self.content.clone()).ok(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(transparent)]
+pub struct DnsRecords(Vec<DnsRecord>);
+
+impl ApiResult for DnsRecords {}
+
+impl From<DnsRecords> for Vec<DnsRecord> {
+    fn from(records: DnsRecords) -> Self {
+        records.0
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Paging {
+    pub page: u32,
+    pub per_page: u32,
+}
+
+#[derive(Debug)]
+pub struct ListDnsRecords<'a> {
+    pub zone_identifier: &'a str,
+    pub params: Paging,
+}
+
+impl<'a> Endpoint<DnsRecords, Paging> for ListDnsRecords<'a> {
+    fn method(&self) -> Method {
+        Method::Get
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    fn query(&self) -> Option<Paging> {
+        Some(self.params.clone())
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DnsRecordParams {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    pub ttl: u32,
+    /// Only set for addresses and CNAMEs, the only records that can be proxied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+}
+
+impl DnsRecordParams {
+    pub fn new(record: &Record, settings: RecordSettings) -> Self {
+        let settings = settings.resolve(record.kind());
+        let mut params = DnsRecordParams {
+            kind: record.kind(),
+            name: record.name(),
+            content: Some(record.value()),
+            priority: None,
+            data: None,
+            ttl: settings.ttl.unwrap_or(AUTOMATIC_TTL),
+            proxied: settings.proxied,
+        };
+        match record {
+            Record::Mx(_, priority, exchange) => {
+                params.content = Some(exchange.clone());
+                params.priority = Some(*priority);
+            }
+            Record::Srv(_, priority, weight, port, target) => {
+                params.content = None;
+                params.data = Some(json!({
+                    "priority": priority,
+                    "weight": weight,
+                    "port": port,
+                    "target": target,
+                }));
+            }
+            Record::Caa(_, flags, tag, value) => {
+                params.content = None;
+                params.data = Some(json!({"flags": flags, "tag": tag, "value": value}));
+            }
+            _ => {}
+        }
+        params
+    }
+}
+
+#[derive(Debug)]
+pub struct CreateDnsRecord<'a> {
+    pub zone_identifier: &'a str,
+    pub params: DnsRecordParams,
+}
+
+impl<'a> Endpoint<DnsRecord, (), DnsRecordParams> for CreateDnsRecord<'a> {
+    fn method(&self) -> Method {
+        Method::Post
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    fn body(&self) -> Option<DnsRecordParams> {
+        Some(self.params.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct UpdateDnsRecord<'a> {
+    pub zone_identifier: &'a str,
+    pub identifier: &'a str,
+    pub params: DnsRecordParams,
+}
+
+impl<'a> Endpoint<DnsRecord, (), DnsRecordParams> for UpdateDnsRecord<'a> {
+    fn method(&self) -> Method {
+        Method::Put
+    }
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/dns_records/{}",
+            self.zone_identifier, self.identifier
+        )
+    }
+    fn body(&self) -> Option<DnsRecordParams> {
+        Some(self.params.clone())
+    }
+}
@@ -0,0 +1,591 @@
+mod endpoints;
+
+use self::endpoints::{CreateDnsRecord, DnsRecordParams, ListDnsRecords, Paging, UpdateDnsRecord};
+use super::{DnsProvider, ZoneRecord};
+use crate::error::Error;
+use crate::model::{Record, RecordSettings};
+use ::cloudflare::endpoints::{dns, zone};
+use ::cloudflare::framework::{
+    apiclient::ApiClient,
+    auth::Credentials,
+    endpoint::{Endpoint, Method},
+    response::{ApiFailure, ApiResult, ApiSuccess},
+    Environment, HttpApiClient, HttpApiClientConfig,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug)]
+struct Config {
+    cloudflare_token: String,
+}
+
+const RECORDS_PER_PAGE: u32 = 100;
+const ZONES_PER_PAGE: u32 = 50;
+
+/// How often to retry a request failing with a transient error, and how long to wait.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    /// The first wait after a server or network error, doubled on every retry.
+    pub base: Duration,
+    /// The first wait after hitting the rate limit, which Cloudflare enforces
+    /// over five minutes.
+    pub rate_limited: Duration,
+    pub max: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Retry {
+        Retry {
+            attempts: 6,
+            base: Duration::from_secs(1),
+            rate_limited: Duration::from_secs(10),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Retry {
+    /// The wait before retry number `retry`, counting from 0.
+    pub fn wait(&self, retry: u32, rate_limited: bool) -> Duration {
+        let base = if rate_limited {
+            self.rate_limited
+        } else {
+            self.base
+        };
+        base.saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max)
+    }
+}
+
+pub struct Cloudflare {
+    api_client: HttpApiClient,
+    retry: Retry,
+    /// Called with the `DnsProvider` method whose request failed and is retried.
+    retried: Option<Box<dyn Fn(&'static str)>>,
+}
+
+impl Cloudflare {
+    /// Connects using the API token in the `CLOUDFLARE_TOKEN` environment variable.
+    pub fn from_env() -> Result<Cloudflare> {
+        let env = envy::from_env::<Config>().map_err(|_| {
+            Error::Auth("Set CLOUDFLARE_TOKEN to a Cloudflare API token".to_string())
+        })?;
+        let credentials = Credentials::UserAuthToken {
+            token: env.cloudflare_token,
+        };
+        let api_client = HttpApiClient::new(
+            credentials,
+            HttpApiClientConfig::default(),
+            Environment::Production,
+        )?;
+        Ok(Cloudflare {
+            api_client,
+            retry: Retry::default(),
+            retried: None,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new(api_client: HttpApiClient) -> Cloudflare {
+        Cloudflare {
+            api_client,
+            retry: Retry {
+                base: Duration::ZERO,
+                rate_limited: Duration::ZERO,
+                ..Retry::default()
+            },
+            retried: None,
+        }
+    }
+
+    /// Calls `retried` with the name of the `DnsProvider` method every time
+    /// one of its requests failed and is retried.
+    pub fn on_retry(mut self, retried: impl Fn(&'static str) + 'static) -> Cloudflare {
+        self.retried = Some(Box::new(retried));
+        self
+    }
+
+    /// Sends a request, retrying it with exponential backoff while it fails
+    /// with a transient error. A create whose response got lost is not retried,
+    /// as Cloudflare may have made the record already.
+    fn request<R: ApiResult, Q: Serialize, B: Serialize>(
+        &self,
+        method: &'static str,
+        endpoint: &dyn Endpoint<R, Q, B>,
+    ) -> Result<ApiSuccess<R>, Error> {
+        let mut retry = 0;
+        loop {
+            let failure = match self.api_client.request(endpoint) {
+                Ok(success) => return Ok(success),
+                Err(failure) => failure,
+            };
+            let rate_limited =
+                matches!(&failure, ApiFailure::Error(status, _) if status.as_u16() == 429);
+            let lost = matches!(&failure, ApiFailure::Invalid(e) if !e.is_connect());
+            let repeatable = !(lost && matches!(endpoint.method(), Method::Post));
+            let error = classify(failure);
+            if !error.is_transient() || !repeatable || retry + 1 >= self.retry.attempts {
+                return Err(error);
+            }
+            if let Some(retried) = &self.retried {
+                retried(method);
+            }
+            let wait = self.retry.wait(retry, rate_limited);
+            verbose!("{}, retrying in {:?}", error, wait);
+            thread::sleep(wait);
+            retry += 1;
+        }
+    }
+}
+
+/// Sorts a failed request into the errors netmgr tells apart.
+fn classify(failure: ApiFailure) -> Error {
+    match failure {
+        ApiFailure::Error(status, errors) => {
+            let mut message = format!("Cloudflare answered with HTTP {}", status);
+            for error in &errors.errors {
+                message.push_str(&format!(", error {}: {}", error.code, error.message));
+            }
+            match status.as_u16() {
+                401 | 403 => Error::Auth(message),
+                429 | 500..=599 => Error::Transient(message),
+                _ => Error::Validation(message),
+            }
+        }
+        ApiFailure::Invalid(e) if e.is_decode() => {
+            Error::Validation(format!("Unable to read the response of Cloudflare: {}", e))
+        }
+        ApiFailure::Invalid(e) => Error::Transient(format!("Unable to reach Cloudflare: {}", e)),
+    }
+}
+
+/// Requests consecutive pages of a list endpoint until the last page has been read.
+fn fetch_all_pages<T, R: Into<Vec<T>>>(
+    per_page: u32,
+    mut request: impl FnMut(u32) -> Result<ApiSuccess<R>, Error>,
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    for page in 1.. {
+        let resp = request(page)?;
+        let total_pages = resp
+            .result_info
+            .as_ref()
+            .and_then(|info| info.get("total_pages"))
+            .and_then(|total| total.as_u64());
+        let result = resp.result.into();
+        let full_page = result.len() as u32 >= per_page;
+        items.extend(result);
+        let more = match total_pages {
+            Some(total) => u64::from(page) < total,
+            None => full_page,
+        };
+        if !more {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+impl DnsProvider for Cloudflare {
+    fn find_zone(&self, domain: &str) -> Result<String> {
+        let zones = fetch_all_pages(ZONES_PER_PAGE, |page| {
+            self.request(
+                "find_zone",
+                &zone::ListZones {
+                    params: zone::ListZonesParams {
+                        name: Some(domain.to_string()),
+                        page: Some(page),
+                        per_page: Some(ZONES_PER_PAGE),
+                        ..Default::default()
+                    },
+                },
+            )
+        })?;
+        let cf_zone = zones
+            .into_iter()
+            .find(|z| z.name == domain)
+            .ok_or_else(|| Error::MissingZone(domain.to_string()))?;
+        verbose!("Found zone {} with id {}", domain, cf_zone.id);
+        Ok(cf_zone.id)
+    }
+
+    fn list_records(&self, zone_id: &str) -> Result<Vec<ZoneRecord>> {
+        let dns_records = fetch_all_pages(RECORDS_PER_PAGE, |page| {
+            self.request(
+                "list_records",
+                &ListDnsRecords {
+                    zone_identifier: zone_id,
+                    params: Paging {
+                        page,
+                        per_page: RECORDS_PER_PAGE,
+                    },
+                },
+            )
+        })?;
+        verbose!("Found {} records in zone {}", dns_records.len(), zone_id);
+        Ok(dns_records
+            .iter()
+            .filter_map(|r| {
+                Some(ZoneRecord {
+                    id: r.id.clone(),
+                    record: r.to_record()?,
+                    settings: r.settings(),
+                })
+            })
+            .collect())
+    }
+
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<String> {
+        let req = CreateDnsRecord {
+            zone_identifier: zone_id,
+            params: DnsRecordParams::new(record, settings),
+        };
+        Ok(self.request("create_record", &req)?.result.id)
+    }
+
+    fn update_record(
+        &self,
+        zone_id: &str,
+        id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<()> {
+        let req = UpdateDnsRecord {
+            zone_identifier: zone_id,
+            identifier: id,
+            params: DnsRecordParams::new(record, settings),
+        };
+        self.request("update_record", &req)?;
+        Ok(())
+    }
+
+    fn delete_record(&self, zone_id: &str, id: &str) -> Result<()> {
+        let req = dns::DeleteDnsRecord {
+            zone_identifier: zone_id,
+            identifier: id,
+        };
+        self.request("delete_record", &req)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockServer};
+    use serde_json::{json, Value};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn list_records_reads_every_page() {
+        let records: Vec<Value> = (0..250)
+            .map(|i| {
+                mock::dns_record(
+                    &format!("id{}", i),
+                    "A",
+                    &format!("host{}.example.com", i),
+                    "10.0.0.1",
+                )
+            })
+            .collect();
+        let server = MockServer::start(move |req| (200, mock::paginate(req, &records)));
+
+        let records = Cloudflare::new(server.client())
+            .list_records("zone")
+            .unwrap();
+
+        assert_eq!(records.len(), 250);
+        assert_eq!(records[249].id, "id249");
+        let pages: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|r| r.query["page"].clone())
+            .collect();
+        assert_eq!(pages, vec!["1", "2", "3"]);
+        assert!(server
+            .requests()
+            .iter()
+            .all(|r| r.method == "GET" && r.path == "/client/v4/zones/zone/dns_records"));
+    }
+
+    #[test]
+    fn list_records_reads_ptr_records_and_skips_unknown_types() {
+        let server = MockServer::start(|req| {
+            let records = vec![
+                mock::dns_record("1", "PTR", "1.0.0.10.in-addr.arpa", "gw.example.com"),
+                mock::dns_record("2", "LOC", "gw.example.com", "52 22 23 N 4 53 32 E 0m"),
+            ];
+            (200, mock::paginate(req, &records))
+        });
+
+        let records = Cloudflare::new(server.client())
+            .list_records("zone")
+            .unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].record,
+            Record::Ptr(
+                "1.0.0.10.in-addr.arpa".to_string(),
+                "gw.example.com".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn list_records_reads_typed_records() {
+        let server = MockServer::start(|req| {
+            let mut mx = mock::dns_record("1", "MX", "example.com", "mail.example.com");
+            mx["priority"] = 10.into();
+            let mut srv = mock::dns_record(
+                "2",
+                "SRV",
+                "_ldap._tcp.example.com",
+                "5 389 ldap.example.com",
+            );
+            srv["priority"] = 10.into();
+            srv["data"] = serde_json::json!({"priority": 10, "weight": 5, "port": 389, "target": "ldap.example.com"});
+            let mut caa =
+                mock::dns_record("3", "CAA", "example.com", "0 issue \"letsencrypt.org\"");
+            caa["data"] =
+                serde_json::json!({"flags": 0, "tag": "issue", "value": "letsencrypt.org"});
+            (200, mock::paginate(req, &[mx, srv, caa]))
+        });
+
+        let records: Vec<Record> = Cloudflare::new(server.client())
+            .list_records("zone")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.record)
+            .collect();
+
+        assert_eq!(
+            records,
+            vec![
+                Record::Mx(
+                    "example.com".to_string(),
+                    10,
+                    "mail.example.com".to_string()
+                ),
+                Record::Srv(
+                    "_ldap._tcp.example.com".to_string(),
+                    10,
+                    5,
+                    389,
+                    "ldap.example.com".to_string()
+                ),
+                Record::Caa(
+                    "example.com".to_string(),
+                    0,
+                    "issue".to_string(),
+                    "letsencrypt.org".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn records_are_written_with_their_settings() {
+        let server = MockServer::start(|_| {
+            let mut record = mock::dns_record("1", "CNAME", "www.example.com", "example.com");
+            record["ttl"] = 300.into();
+            (200, mock::success(record))
+        });
+        let cloudflare = Cloudflare::new(server.client());
+        let www = Record::Cname("www.example.com".to_string(), "example.com".to_string());
+        let mx = Record::Mx(
+            "example.com".to_string(),
+            10,
+            "mail.example.com".to_string(),
+        );
+
+        cloudflare
+            .create_record("zone", &www, RecordSettings::new(Some(300), Some(true)))
+            .unwrap();
+        cloudflare
+            .update_record("zone", "1", &mx, RecordSettings::new(None, Some(true)))
+            .unwrap();
+
+        let bodies: Vec<Value> = server
+            .requests()
+            .iter()
+            .map(|r| serde_json::from_str(&r.body).unwrap())
+            .collect();
+        assert_eq!(
+            (&bodies[0]["ttl"], &bodies[0]["proxied"]),
+            (&1.into(), &true.into())
+        );
+        assert_eq!(
+            (&bodies[1]["ttl"], &bodies[1]["proxied"]),
+            (&1.into(), &Value::Null)
+        );
+
+        let listed = mock::dns_record("1", "A", "a.example.com", "10.0.0.1");
+        let record: super::endpoints::DnsRecord = serde_json::from_value(listed).unwrap();
+        assert_eq!(record.settings(), RecordSettings::new(None, Some(false)));
+    }
+
+    #[test]
+    fn find_zone_reads_every_page() {
+        let zones: Vec<Value> = (0..75)
+            .map(|i| mock::zone(&format!("zone{}", i), &format!("example{}.com", i)))
+            .collect();
+        let server = MockServer::start(move |req| (200, mock::paginate(req, &zones)));
+
+        let id = Cloudflare::new(server.client())
+            .find_zone("example70.com")
+            .unwrap();
+
+        assert_eq!(id, "zone70");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].query["name"], "example70.com");
+    }
+
+    #[test]
+    fn fetch_all_pages_stops_on_short_page_without_result_info() {
+        let server = MockServer::start(|req| {
+            let n = if req.query["page"] == "1" { 100 } else { 3 };
+            let records: Vec<Value> = (0..n)
+                .map(|i| mock::dns_record(&i.to_string(), "A", "a.example.com", "10.0.0.1"))
+                .collect();
+            (200, mock::success(Value::Array(records)))
+        });
+
+        let records = Cloudflare::new(server.client())
+            .list_records("zone")
+            .unwrap();
+
+        assert_eq!(records.len(), 103);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    fn failure(code: u16, message: &str) -> Value {
+        json!({
+            "success": false,
+            "errors": [{"code": code, "message": message}],
+            "messages": [],
+            "result": null
+        })
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |req| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => (429, failure(10000, "Rate limited")),
+            1 => (502, failure(10000, "Bad gateway")),
+            _ => (200, mock::paginate(req, &[])),
+        });
+
+        let retried = Rc::new(RefCell::new(Vec::new()));
+        let records = Cloudflare::new(server.client())
+            .on_retry({
+                let retried = retried.clone();
+                move |method| retried.borrow_mut().push(method)
+            })
+            .list_records("zone")
+            .unwrap();
+
+        assert!(records.is_empty());
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(*retried.borrow(), vec!["list_records", "list_records"]);
+    }
+
+    #[test]
+    fn retries_give_up_after_the_last_attempt() {
+        let server = MockServer::start(|_| (503, failure(10000, "Unavailable")));
+
+        let err = Cloudflare::new(server.client())
+            .delete_record("zone", "1")
+            .unwrap_err();
+
+        assert!(err.downcast_ref::<Error>().unwrap().is_transient());
+        assert_eq!(server.requests().len(), Retry::default().attempts as usize);
+    }
+
+    #[test]
+    fn failures_are_told_apart() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" => (200, mock::paginate(req, &[mock::zone("1", "example.org")])),
+            "DELETE" => (403, failure(10000, "Authentication error")),
+            _ => (400, failure(1004, "DNS Validation Error")),
+        });
+        let cloudflare = Cloudflare::new(server.client());
+        let kind = |result: Result<()>| result.unwrap_err().downcast::<Error>().unwrap();
+
+        assert_eq!(
+            kind(cloudflare.find_zone("example.com").map(|_| ())),
+            Error::MissingZone("example.com".to_string())
+        );
+        assert!(matches!(
+            kind(cloudflare.delete_record("zone", "1")),
+            Error::Auth(_)
+        ));
+        let www = Record::Cname("www.example.com".to_string(), "example.com".to_string());
+        assert_eq!(
+            kind(
+                cloudflare
+                    .create_record("zone", &www, RecordSettings::default())
+                    .map(|_| ())
+            ),
+            Error::Validation(
+                "Cloudflare answered with HTTP 400 Bad Request, error 1004: DNS Validation Error"
+                    .to_string()
+            )
+        );
+        // Only the transient failures above are retried.
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn rate_limits_wait_longer() {
+        let retry = Retry::default();
+        let waits: Vec<u64> = (0..5).map(|n| retry.wait(n, false).as_secs()).collect();
+        assert_eq!(waits, vec![1, 2, 4, 8, 16]);
+        let waits: Vec<u64> = (0..5).map(|n| retry.wait(n, true).as_secs()).collect();
+        assert_eq!(waits, vec![10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn creates_are_not_repeated_once_sent() {
+        // Reads each request and hangs up without answering.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/client/v4/", listener.local_addr().unwrap());
+        let accepted = std::sync::Arc::new(AtomicUsize::new(0));
+        let count = accepted.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                count.fetch_add(1, Ordering::SeqCst);
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = std::io::Read::read(&mut stream, &mut buf);
+            }
+        });
+        let cloudflare = Cloudflare::new(mock::client(&url));
+        let www = Record::Cname("www.example.com".to_string(), "example.com".to_string());
+
+        let err = cloudflare
+            .create_record("zone", &www, RecordSettings::default())
+            .unwrap_err();
+        assert!(err.downcast_ref::<Error>().unwrap().is_transient());
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        cloudflare.delete_record("zone", "1").unwrap_err();
+        assert_eq!(
+            accepted.load(Ordering::SeqCst),
+            1 + Retry::default().attempts as usize
+        );
+    }
+}
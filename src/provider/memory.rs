@@ -0,0 +1,168 @@
+use super::{DnsProvider, ZoneRecord};
+use crate::model::{Record, RecordSettings};
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+/// A change made through the provider, in the order it was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    Create(Record),
+    Update(String, Record),
+    Delete(String),
+}
+
+/// A provider keeping its zones in memory and recording every mutation.
+/// Like Cloudflare it refuses to put a CNAME next to other records.
+#[derive(Default)]
+pub struct InMemory {
+    zones: HashMap<String, String>,
+    records: RefCell<BTreeMap<String, (String, Record, RecordSettings)>>,
+    next_id: RefCell<usize>,
+    mutations: RefCell<Vec<Mutation>>,
+}
+
+impl InMemory {
+    pub fn new(domain: &str) -> InMemory {
+        let mut provider = InMemory::default();
+        provider.add_zone(domain);
+        provider
+    }
+
+    pub fn add_zone(&mut self, domain: &str) -> String {
+        let id = format!("zone-{}", domain);
+        self.zones.insert(domain.to_string(), id.clone());
+        id
+    }
+
+    /// Adds records to the zone without recording them as mutations.
+    pub fn with_records(self, domain: &str, records: Vec<Record>) -> InMemory {
+        let zone_id = self.zones[domain].clone();
+        for record in records {
+            let id = self.next_id();
+            self.records
+                .borrow_mut()
+                .insert(id, (zone_id.clone(), record, RecordSettings::default()));
+        }
+        self
+    }
+
+    pub fn records(&self, domain: &str) -> Vec<Record> {
+        let zone_id = &self.zones[domain];
+        let mut records: Vec<Record> = self
+            .records
+            .borrow()
+            .values()
+            .filter(|(z, ..)| z == zone_id)
+            .map(|(_, r, _)| r.clone())
+            .collect();
+        records.sort_by_key(|r| (r.name(), r.kind(), r.value()));
+        records
+    }
+
+    pub fn mutations(&self) -> Vec<Mutation> {
+        self.mutations.borrow().clone()
+    }
+
+    fn next_id(&self) -> String {
+        let mut next = self.next_id.borrow_mut();
+        *next += 1;
+        format!("record-{}", next)
+    }
+
+    fn check_cname_conflict(
+        &self,
+        zone_id: &str,
+        skip: Option<&str>,
+        record: &Record,
+    ) -> Result<()> {
+        let conflict = self.records.borrow().iter().any(|(id, (z, r, _))| {
+            z == zone_id
+                && Some(id.as_str()) != skip
+                && r.name() == record.name()
+                && (r.kind() == "CNAME" || record.kind() == "CNAME")
+        });
+        if conflict {
+            return Err(anyhow!(
+                "A CNAME cannot share the name {} with other records",
+                record.name()
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_exists(&self, zone_id: &str, id: &str) -> Result<()> {
+        match self.records.borrow().get(id) {
+            Some((z, ..)) if z == zone_id => Ok(()),
+            _ => Err(anyhow!("No record {} in zone {}", id, zone_id)),
+        }
+    }
+}
+
+impl DnsProvider for InMemory {
+    fn find_zone(&self, domain: &str) -> Result<String> {
+        self.zones
+            .get(domain)
+            .cloned()
+            .ok_or(anyhow!("Unable to find the zone in your account"))
+    }
+
+    fn list_records(&self, zone_id: &str) -> Result<Vec<ZoneRecord>> {
+        Ok(self
+            .records
+            .borrow()
+            .iter()
+            .filter(|(_, (z, ..))| z == zone_id)
+            .map(|(id, (_, record, settings))| ZoneRecord {
+                id: id.clone(),
+                record: record.clone(),
+                settings: *settings,
+            })
+            .collect())
+    }
+
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<String> {
+        self.check_cname_conflict(zone_id, None, record)?;
+        let id = self.next_id();
+        self.records
+            .borrow_mut()
+            .insert(id.clone(), (zone_id.to_string(), record.clone(), settings));
+        self.mutations
+            .borrow_mut()
+            .push(Mutation::Create(record.clone()));
+        Ok(id)
+    }
+
+    fn update_record(
+        &self,
+        zone_id: &str,
+        id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<()> {
+        self.check_exists(zone_id, id)?;
+        self.check_cname_conflict(zone_id, Some(id), record)?;
+        self.records.borrow_mut().insert(
+            id.to_string(),
+            (zone_id.to_string(), record.clone(), settings),
+        );
+        self.mutations
+            .borrow_mut()
+            .push(Mutation::Update(id.to_string(), record.clone()));
+        Ok(())
+    }
+
+    fn delete_record(&self, zone_id: &str, id: &str) -> Result<()> {
+        self.check_exists(zone_id, id)?;
+        self.records.borrow_mut().remove(id);
+        self.mutations
+            .borrow_mut()
+            .push(Mutation::Delete(id.to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+mod cloudflare;
+#[cfg(test)]
+pub mod memory;
+
+use crate::model::{Record, RecordSet, RecordSettings};
+use crate::plan::{Change, Plan};
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+pub use self::cloudflare::Cloudflare;
+
+/// Records created by netmgr get a sibling TXT record at `_netmgr.<name>`
+/// holding `OWNER_MARKER` and their type, like `heritage=netmgr,type=A`.
+/// Only records carrying a marker for their type are ever deleted.
+const OWNER_PREFIX: &str = "_netmgr";
+const OWNER_MARKER: &str = "heritage=netmgr";
+
+fn owner_record(name: &str, kind: &str) -> Record {
+    Record::Txt(
+        format!("{}.{}", OWNER_PREFIX, name),
+        format!("{},type={}", OWNER_MARKER, kind),
+    )
+}
+
+/// The type of records a marker with `content` owns, if it is one.
+fn owned_type(content: &str) -> Option<&str> {
+    content
+        .trim_matches('"')
+        .strip_prefix(OWNER_MARKER)?
+        .strip_prefix(",type=")
+}
+
+/// A record as stored by a provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneRecord {
+    pub id: String,
+    pub record: Record,
+    pub settings: RecordSettings,
+}
+
+/// A DNS backend netmgr can publish records to.
+pub trait DnsProvider {
+    /// Returns the provider's identifier for the zone serving `domain`.
+    fn find_zone(&self, domain: &str) -> Result<String>;
+    /// Lists every record in the zone that netmgr is able to represent.
+    fn list_records(&self, zone_id: &str) -> Result<Vec<ZoneRecord>>;
+    /// Creates `record` and returns its id.
+    fn create_record(
+        &self,
+        zone_id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<String>;
+    fn update_record(
+        &self,
+        zone_id: &str,
+        id: &str,
+        record: &Record,
+        settings: RecordSettings,
+    ) -> Result<()>;
+    fn delete_record(&self, zone_id: &str, id: &str) -> Result<()>;
+}
+
+/// The records currently in a zone, split into the records themselves and
+/// the ownership markers netmgr keeps next to them.
+pub struct LiveState {
+    pub record_ids: HashMap<Record, String>,
+    pub records: Vec<Record>,
+    pub settings: HashMap<Record, RecordSettings>,
+    /// Names and types of records owned by netmgr, mapped to the id of their marker record.
+    pub owned: HashMap<(String, String), String>,
+}
+
+impl LiveState {
+    pub fn fetch(provider: &dyn DnsProvider, zone_id: &str) -> Result<LiveState> {
+        Ok(LiveState::new(provider.list_records(zone_id)?))
+    }
+
+    pub fn new(zone_records: Vec<ZoneRecord>) -> LiveState {
+        let mut live = LiveState {
+            record_ids: HashMap::new(),
+            records: Vec::new(),
+            settings: HashMap::new(),
+            owned: HashMap::new(),
+        };
+        let marker_prefix = format!("{}.", OWNER_PREFIX);
+        for ZoneRecord {
+            id,
+            record,
+            settings,
+        } in zone_records
+        {
+            if let Record::Txt(name, content) = &record {
+                if let Some(owned) = name.strip_prefix(&marker_prefix) {
+                    if let Some(kind) = owned_type(content) {
+                        live.owned.insert((owned.to_string(), kind.to_string()), id);
+                        continue;
+                    }
+                }
+            }
+            live.record_ids.insert(record.clone(), id);
+            live.settings
+                .insert(record.clone(), settings.resolve(record.kind()));
+            live.records.push(record);
+        }
+        live
+    }
+
+    /// The records grouped into sets along with their settings.
+    pub fn record_sets(&self) -> Vec<RecordSet> {
+        RecordSet::group_with(
+            self.records
+                .iter()
+                .map(|r| (r.clone(), self.settings[r]))
+                .collect(),
+        )
+    }
+}
+
+/// Executes `plan` against a zone whose current state is `live`.
+pub fn apply(
+    provider: &dyn DnsProvider,
+    zone_id: &str,
+    live: &LiveState,
+    plan: Plan,
+) -> Result<()> {
+    apply_counted(provider, zone_id, live, plan, |_| ())
+}
+
+/// Like `apply`, calling `applied` with every change once it went through,
+/// so the changes made before a failure are accounted for too.
+pub fn apply_counted(
+    provider: &dyn DnsProvider,
+    zone_id: &str,
+    live: &LiveState,
+    plan: Plan,
+    mut applied: impl FnMut(&Change),
+) -> Result<()> {
+    let mut owned: HashSet<(String, String)> = live.owned.keys().cloned().collect();
+    let mut deleted: HashSet<Record> = HashSet::new();
+    let mut kept: HashSet<(String, String)> = HashSet::new();
+    // Deletions go first so a name can switch between a CNAME and other types.
+    let mut changes = plan.changes;
+    changes.sort_by_key(|c| !matches!(c, Change::Delete { .. }));
+
+    for change in changes {
+        if crate::cli::verbosity() >= 0 {
+            println!("{}", change);
+        }
+        let (old, new) = match &change {
+            Change::Create { set } => (None, Some(set)),
+            Change::Update { old, new } => (Some(old), Some(new)),
+            Change::Delete { set } => (Some(set), None),
+        };
+        let removed = values_not_in(old, new)?;
+        let mut added = values_not_in(new, old)?.into_iter();
+        let settings = new.map(|s| s.settings).unwrap_or_default();
+        let record_id = |record: &Record| {
+            live.record_ids
+                .get(record)
+                .ok_or(anyhow!("Unable to find record id for {}", record.name()))
+        };
+
+        // Reuse the ids of removed values for added ones before deleting or creating anything.
+        for record in removed {
+            let id = record_id(&record)?;
+            match added.next() {
+                Some(value) => provider.update_record(zone_id, id, &value, settings)?,
+                None => {
+                    provider.delete_record(zone_id, id)?;
+                    deleted.insert(record);
+                }
+            }
+        }
+        for record in added {
+            provider.create_record(zone_id, &record, settings)?;
+        }
+        // Values kept in place still need updating when only their settings changed.
+        if let (Some(old), Some(new)) = (old, new) {
+            for record in values_in_both(old, new)? {
+                if live.settings.get(&record) != Some(&settings) {
+                    provider.update_record(zone_id, record_id(&record)?, &record, settings)?;
+                }
+            }
+        }
+        if let Some(new) = new {
+            let key = (new.name.clone(), new.kind.clone());
+            if !owned.contains(&key) {
+                provider.create_record(
+                    zone_id,
+                    &owner_record(&key.0, &key.1),
+                    RecordSettings::default(),
+                )?;
+                owned.insert(key.clone());
+            }
+            kept.insert(key);
+        }
+        applied(&change);
+    }
+
+    // Drop the ownership markers whose records are all gone.
+    kept.extend(
+        live.records
+            .iter()
+            .filter(|r| !deleted.contains(r))
+            .map(|r| (r.name(), r.kind().to_string())),
+    );
+    let deleted: HashSet<(String, String)> = deleted
+        .iter()
+        .map(|r| (r.name(), r.kind().to_string()))
+        .collect();
+    for (key, marker_id) in &live.owned {
+        if deleted.contains(key) && !kept.contains(key) {
+            provider.delete_record(zone_id, marker_id)?;
+        }
+    }
+    if crate::cli::verbosity() >= 0 {
+        println!("Apply complete.");
+    }
+    Ok(())
+}
+
+/// The records for the values of `new` that `old` already has.
+fn values_in_both(old: &RecordSet, new: &RecordSet) -> Result<Vec<Record>> {
+    new.values
+        .intersection(&old.values)
+        .map(|v| new.record(v))
+        .collect()
+}
+
+/// The records for the values of `set` that are missing from `other`.
+fn values_not_in(set: Option<&RecordSet>, other: Option<&RecordSet>) -> Result<Vec<Record>> {
+    match set {
+        Some(set) => set
+            .values
+            .iter()
+            .filter(|v| !other.is_some_and(|o| o.values.contains(*v)))
+            .map(|v| set.record(v))
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::memory::{InMemory, Mutation};
+    use super::*;
+    use crate::model::Zone;
+
+    const CONFIG: &str = "
+domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+      - name: nas
+        private_ip: [10.0.0.2, 10.0.0.3]
+        alias: [files]
+";
+
+    fn zone(config: &str) -> Zone {
+        serde_yaml::from_str(config).unwrap()
+    }
+
+    fn plan(provider: &InMemory, zone: &Zone) -> (String, LiveState, Plan) {
+        let zone_id = provider.find_zone(&zone.domain).unwrap();
+        let live = LiveState::fetch(provider, &zone_id).unwrap();
+        let plan = Plan::new(
+            &zone.domain,
+            live.record_sets(),
+            zone.record_sets(zone.backends.cloudflare),
+            &live.owned,
+        );
+        (zone_id, live, plan)
+    }
+
+    fn sync(provider: &InMemory, zone: &Zone) {
+        let (zone_id, live, plan) = plan(provider, zone);
+        apply(provider, &zone_id, &live, plan).unwrap();
+    }
+
+    fn a(name: &str, ip: &str) -> Record {
+        Record::A(name.to_string(), ip.to_string())
+    }
+
+    #[test]
+    fn apply_is_idempotent() {
+        let provider = InMemory::new("example.com");
+        let zone = zone(CONFIG);
+
+        sync(&provider, &zone);
+        let mutations = provider.mutations();
+
+        let live = LiveState::new(provider.list_records("zone-example.com").unwrap());
+        let mut expected = zone.all_records();
+        expected.sort_by_key(|r| (r.name(), r.kind(), r.value()));
+        let mut records = live.records.clone();
+        records.sort_by_key(|r| (r.name(), r.kind(), r.value()));
+        assert_eq!(records, expected);
+        assert_eq!(live.owned.len(), 7);
+
+        let (_, _, second) = plan(&provider, &zone);
+        assert!(second.is_empty(), "{}", second);
+        sync(&provider, &zone);
+        assert_eq!(provider.mutations(), mutations);
+    }
+
+    #[test]
+    fn removed_records_are_deleted_with_their_marker() {
+        let provider = InMemory::new("example.com");
+        sync(&provider, &zone(CONFIG));
+
+        let smaller = zone(&CONFIG.replace("        alias: [files]\n", ""));
+        sync(&provider, &smaller);
+
+        let names: Vec<String> = provider
+            .records("example.com")
+            .iter()
+            .map(|r| r.name())
+            .collect();
+        assert!(!names.iter().any(|n| n.starts_with("files.")));
+        assert!(!names.iter().any(|n| n.starts_with("_netmgr.files.")));
+        assert!(plan(&provider, &smaller).2.is_empty());
+    }
+
+    #[test]
+    fn records_not_owned_by_netmgr_are_left_alone() {
+        let manual = a("manual.office.example.com", "192.168.1.1");
+        let provider =
+            InMemory::new("example.com").with_records("example.com", vec![manual.clone()]);
+
+        let (_, _, plan) = plan(&provider, &zone(CONFIG));
+        assert_eq!(plan.ignored, RecordSet::group(vec![manual.clone()]));
+
+        sync(&provider, &zone(CONFIG));
+        assert!(provider.records("example.com").contains(&manual));
+    }
+
+    #[test]
+    fn changed_values_reuse_existing_records() {
+        let provider = InMemory::new("example.com");
+        sync(&provider, &zone(CONFIG));
+        let before = provider.mutations().len();
+
+        sync(&provider, &zone(&CONFIG.replace("10.0.0.3", "10.0.0.4")));
+
+        match &provider.mutations()[before..] {
+            [Mutation::Update(_, record)] => {
+                assert_eq!(record, &a("nas.office.int.example.com", "10.0.0.4"))
+            }
+            other => panic!("expected a single update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_cname_can_become_an_address() {
+        let provider = InMemory::new("example.com");
+        sync(&provider, &zone(CONFIG));
+
+        let promoted = CONFIG.replace(
+            "        alias: [files]\n",
+            "      - name: files\n        private_ip: 10.0.0.9\n",
+        );
+        sync(&provider, &zone(&promoted));
+
+        assert!(provider
+            .records("example.com")
+            .contains(&a("files.office.int.example.com", "10.0.0.9")));
+    }
+
+    #[test]
+    fn custom_records_are_synced() {
+        let provider = InMemory::new("example.com");
+        let config = format!(
+            "{}records:\n  - type: MX\n    priority: 10\n    value: mail.example.com\n",
+            CONFIG
+        );
+        sync(&provider, &zone(&config));
+        sync(
+            &provider,
+            &zone(&config.replace("priority: 10", "priority: 20")),
+        );
+
+        let mx: Vec<Record> = provider
+            .records("example.com")
+            .into_iter()
+            .filter(|r| r.kind() == "MX")
+            .collect();
+        assert_eq!(
+            mx,
+            vec![Record::Mx(
+                "example.com".to_string(),
+                20,
+                "mail.example.com".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn owning_a_type_leaves_other_types_at_the_name_alone() {
+        let verification = Record::Txt(
+            "example.com".to_string(),
+            "google-site-verification=abc".to_string(),
+        );
+        let provider =
+            InMemory::new("example.com").with_records("example.com", vec![verification.clone()]);
+        let config = format!(
+            "{}records:\n  - type: MX\n    priority: 10\n    value: mail.example.com\n",
+            CONFIG
+        );
+        sync(&provider, &zone(&config));
+
+        let (_, _, second) = plan(&provider, &zone(&config));
+        assert!(second.is_empty(), "{}", second);
+        assert_eq!(second.ignored, RecordSet::group(vec![verification.clone()]));
+        sync(&provider, &zone(CONFIG));
+
+        let records = provider.records("example.com");
+        assert!(records.contains(&verification));
+        assert!(!records.iter().any(|r| r.kind() == "MX"));
+        assert!(!records.iter().any(|r| r.name() == "_netmgr.example.com"));
+    }
+
+    #[test]
+    fn changes_are_counted_as_they_are_applied() {
+        let provider = InMemory::new("example.com");
+        let (zone_id, live, mut plan) = plan(&provider, &zone(CONFIG));
+        let missing = RecordSet::group(vec![a("gone.example.com", "10.0.0.9")]).remove(0);
+        plan.changes.push(Change::Update {
+            old: missing.clone(),
+            new: missing,
+        });
+        let creates = plan.changes.len() - 1;
+
+        let mut applied = Vec::new();
+        let result = apply_counted(&provider, &zone_id, &live, plan, |change| {
+            applied.push(change.clone())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(applied.len(), creates);
+        assert!(applied.iter().all(|c| matches!(c, Change::Create { .. })));
+    }
+
+    #[test]
+    fn records_of_an_unowned_type_are_not_overwritten() {
+        let verification = Record::Txt(
+            "example.com".to_string(),
+            "google-site-verification=abc".to_string(),
+        );
+        let provider =
+            InMemory::new("example.com").with_records("example.com", vec![verification.clone()]);
+        let config = format!(
+            "{}records:\n  - type: TXT\n    value: v=spf1 -all\n",
+            CONFIG
+        );
+
+        let (_, _, plan) = plan(&provider, &zone(&config));
+        assert!(!plan.changes.iter().any(|c| c.set().kind == "TXT"));
+        assert_eq!(plan.ignored, RecordSet::group(vec![verification.clone()]));
+        sync(&provider, &zone(&config));
+
+        let txt: Vec<Record> = provider
+            .records("example.com")
+            .into_iter()
+            .filter(|r| r.name() == "example.com" && r.kind() == "TXT")
+            .collect();
+        assert_eq!(txt, vec![verification]);
+    }
+
+    #[test]
+    fn changed_settings_update_records_in_place() {
+        let provider = InMemory::new("example.com");
+        sync(&provider, &zone(CONFIG));
+        let before = provider.mutations().len();
+
+        let config = CONFIG.replace(
+            "        private_ip: 10.0.0.1\n",
+            "        private_ip: 10.0.0.1\n        ttl: 300\n",
+        );
+        let (_, _, changes) = plan(&provider, &zone(&config));
+        let changes: Vec<String> = changes.changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec!["  ~ gw.office.int.example.com A 10.0.0.1 -> 10.0.0.1 (ttl 300)"]
+        );
+        sync(&provider, &zone(&config));
+
+        let updated: Vec<String> = provider.mutations()[before..]
+            .iter()
+            .filter_map(|m| match m {
+                Mutation::Update(_, record) => Some(record.value()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(updated, vec!["10.0.0.1"]);
+        let live = LiveState::fetch(&provider, "zone-example.com").unwrap();
+        assert_eq!(
+            live.settings[&a("gw.office.int.example.com", "10.0.0.1")].ttl,
+            Some(300)
+        );
+        assert!(plan(&provider, &zone(&config)).2.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+//! PTR records for the private addresses of every server, grouped into reverse zones.
+use crate::model::{Network, Record, Zone};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// A reverse zone such as `0.10.in-addr.arpa` and the PTR records in it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReverseZone {
+    pub origin: String,
+    pub records: Vec<Record>,
+}
+
+/// Derives the reverse zones of `zone`. A network's subnet decides the zone
+/// its addresses go in, rounded to the nearest octet (or nibble for IPv6)
+/// boundary. Without a subnet IPv4 addresses are grouped by /24 and IPv6 by /64.
+pub fn reverse_zones(zone: &Zone) -> Vec<ReverseZone> {
+    let mut zones: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+    for network in &zone.networks {
+        let private_domain = zone.private_domain(network);
+        for server in &network.servers {
+            let target = format!("{}.{}", server.name, private_domain);
+            for ip in server.private_ip.iter().chain(&server.private_ipv6) {
+                let ip: IpAddr = match ip.parse() {
+                    Ok(ip) => ip,
+                    Err(_) => continue,
+                };
+                zones
+                    .entry(origin(ip, zone_prefix(network, ip)))
+                    .or_default()
+                    .push(Record::Ptr(ptr_name(ip), target.clone()));
+            }
+        }
+    }
+    zones
+        .into_iter()
+        .map(|(origin, mut records)| {
+            records.sort_by_key(|r| (r.name(), r.value()));
+            ReverseZone { origin, records }
+        })
+        .collect()
+}
+
+/// The name of the PTR record for `ip`, e.g. `1.0.0.10.in-addr.arpa`.
+pub fn ptr_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ip) => {
+            let mut labels: Vec<String> = ip
+                .octets()
+                .iter()
+                .flat_map(|b| [b >> 4, b & 0xf])
+                .map(|n| format!("{:x}", n))
+                .collect();
+            labels.reverse();
+            format!("{}.ip6.arpa", labels.join("."))
+        }
+    }
+}
+
+/// The reverse zone covering the first `prefix` bits of `ip`.
+fn origin(ip: IpAddr, prefix: u8) -> String {
+    let (bits_per_label, labels) = match ip {
+        IpAddr::V4(_) => (8, 4),
+        IpAddr::V6(_) => (4, 32),
+    };
+    let keep = (prefix / bits_per_label) as usize;
+    let name = ptr_name(ip);
+    let parts: Vec<&str> = name.split('.').collect();
+    parts[labels - keep..].join(".")
+}
+
+fn zone_prefix(network: &Network, ip: IpAddr) -> u8 {
+    let subnet = network
+        .subnet
+        .iter()
+        .filter_map(|s| s.parse::<IpNet>().ok())
+        .find(|net| net.contains(&ip));
+    match (ip, subnet) {
+        (IpAddr::V4(_), Some(net)) => round_up(net.prefix_len(), 8).clamp(8, 24),
+        (IpAddr::V6(_), Some(net)) => round_up(net.prefix_len(), 4).clamp(4, 124),
+        (IpAddr::V4(_), None) => 24,
+        (IpAddr::V6(_), None) => 64,
+    }
+}
+
+fn round_up(n: u8, to: u8) -> u8 {
+    n.div_ceil(to) * to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ptr_names() {
+        assert_eq!(
+            ptr_name("10.0.1.2".parse().unwrap()),
+            "2.1.0.10.in-addr.arpa"
+        );
+        assert_eq!(
+            ptr_name("fd00::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn groups_records_by_subnet() {
+        let zone: Zone = serde_yaml::from_str(
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    subnet: [10.0.0.0/22, fd00::/48]
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00::1
+      - name: nas
+        private_ip: 10.0.1.2
+  - name: lab
+    root: lab1
+    servers:
+      - name: lab1
+        private_ip: 192.168.7.1
+",
+        )
+        .unwrap();
+        let zones: Vec<(String, Vec<String>)> = reverse_zones(&zone)
+            .into_iter()
+            .map(|z| {
+                let names = z
+                    .records
+                    .iter()
+                    .map(|r| format!("{} {}", r.name(), r.value()))
+                    .collect();
+                (z.origin, names)
+            })
+            .collect();
+        assert_eq!(
+            zones,
+            vec![
+                (
+                    "0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa".to_string(),
+                    vec![format!(
+                        "{} gw.office.int.example.com",
+                        ptr_name("fd00::1".parse().unwrap())
+                    )]
+                ),
+                (
+                    "0.0.10.in-addr.arpa".to_string(),
+                    vec!["1.0.0.10.in-addr.arpa gw.office.int.example.com".to_string()]
+                ),
+                (
+                    "1.0.10.in-addr.arpa".to_string(),
+                    vec!["2.1.0.10.in-addr.arpa nas.office.int.example.com".to_string()]
+                ),
+                (
+                    "7.168.192.in-addr.arpa".to_string(),
+                    vec!["1.7.168.192.in-addr.arpa lab1.lab.int.example.com".to_string()]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! An authoritative DNS server for the private records of a zone.
+mod wire;
+
+use crate::export;
+use crate::model::{Record, Zone};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use wire::{Answer, Query, Question, Rdata, Response};
+
+/// The records served for the zone and their TTLs, indexed by lower case name.
+/// With only the private records selected the zone is `<private_prefix>.<domain>`.
+pub struct Authority {
+    origin: String,
+    soa: Answer,
+    records: BTreeMap<String, Vec<(Rdata, u32)>>,
+}
+
+impl Authority {
+    pub fn new(zone: &Zone) -> Authority {
+        let filter = zone.backends.serve;
+        let origin = if filter.public() {
+            zone.domain.to_ascii_lowercase()
+        } else {
+            zone.private_origin().to_ascii_lowercase()
+        };
+        let ttl = zone.soa.ttl;
+        let mut records: BTreeMap<String, Vec<(Rdata, u32)>> = BTreeMap::new();
+        let nameservers = zone.soa.nameservers(&origin);
+        for ns in &nameservers {
+            records
+                .entry(origin.clone())
+                .or_default()
+                .push((Rdata::Ns(ns.clone()), ttl));
+        }
+        for (record, settings) in zone.records_with_settings(filter) {
+            let rdata = match &record {
+                Record::A(_, ip) => ip.parse().ok().map(Rdata::A),
+                Record::Aaaa(_, ip) => ip.parse().ok().map(Rdata::Aaaa),
+                Record::Cname(_, target) => Some(Rdata::Cname(target.clone())),
+                Record::Txt(_, text) => Some(Rdata::Txt(text.clone())),
+                Record::Ptr(_, target) => Some(Rdata::Ptr(target.clone())),
+                Record::Mx(_, priority, exchange) => Some(Rdata::Mx(*priority, exchange.clone())),
+                Record::Srv(_, priority, weight, port, target) => {
+                    Some(Rdata::Srv(*priority, *weight, *port, target.clone()))
+                }
+                Record::Caa(_, flags, tag, value) => {
+                    Some(Rdata::Caa(*flags, tag.clone(), value.clone()))
+                }
+            };
+            if let Some(rdata) = rdata {
+                records
+                    .entry(record.name().to_ascii_lowercase())
+                    .or_default()
+                    .push((rdata, settings.ttl.unwrap_or(ttl)));
+            }
+        }
+        let soa = Answer {
+            name: origin.clone(),
+            ttl,
+            rdata: Rdata::Soa {
+                mname: nameservers[0].clone(),
+                rname: zone.soa.hostmaster(&origin),
+                serial: export::serial(zone),
+                refresh: export::REFRESH,
+                retry: export::RETRY,
+                expire: export::EXPIRE,
+                minimum: ttl,
+            },
+        };
+        Authority {
+            origin,
+            soa,
+            records,
+        }
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// Answers a query, following CNAMEs as long as they stay inside the zone.
+    pub fn answer(&self, query: &Query) -> Response {
+        let mut response = Response::to(query);
+        let question = match &query.question {
+            Some(q) if query.opcode() == 0 => q,
+            Some(_) => {
+                response.rcode = wire::RCODE_NOTIMP;
+                return response;
+            }
+            None => {
+                response.rcode = wire::RCODE_FORMERR;
+                return response;
+            }
+        };
+        let mut name = question.name.trim_end_matches('.').to_ascii_lowercase();
+        if question.qclass != wire::CLASS_IN || !self.contains(&name) {
+            response.rcode = wire::RCODE_REFUSED;
+            return response;
+        }
+        response.authoritative = true;
+
+        // Bounded so a CNAME loop in the config cannot hang the server.
+        for _ in 0..8 {
+            let rdatas = match self.records.get(&name) {
+                Some(rdatas) => rdatas,
+                None => {
+                    if response.answers.is_empty() && !self.has_children(&name) {
+                        response.rcode = wire::RCODE_NXDOMAIN;
+                    }
+                    break;
+                }
+            };
+            let matching = self.matching(&name, rdatas, question);
+            if !matching.is_empty() {
+                response.answers.extend(matching);
+                break;
+            }
+            match rdatas.iter().find(|(r, _)| matches!(r, Rdata::Cname(_))) {
+                Some((cname @ Rdata::Cname(target), ttl)) => {
+                    response.answers.push(Answer {
+                        name: name.clone(),
+                        ttl: *ttl,
+                        rdata: cname.clone(),
+                    });
+                    name = target.trim_end_matches('.').to_ascii_lowercase();
+                    if !self.contains(&name) {
+                        return response;
+                    }
+                }
+                _ => break,
+            }
+        }
+        if response.answers.is_empty() || response.rcode == wire::RCODE_NXDOMAIN {
+            response.authority.push(self.soa.clone());
+        }
+        response
+    }
+
+    fn matching(&self, name: &str, rdatas: &[(Rdata, u32)], question: &Question) -> Vec<Answer> {
+        let mut matching: Vec<Answer> = rdatas
+            .iter()
+            .filter(|(r, _)| question.qtype == wire::TYPE_ANY || r.rtype() == question.qtype)
+            .map(|(r, ttl)| Answer {
+                name: name.to_string(),
+                ttl: *ttl,
+                rdata: r.clone(),
+            })
+            .collect();
+        if name == self.origin
+            && (question.qtype == wire::TYPE_SOA || question.qtype == wire::TYPE_ANY)
+        {
+            matching.insert(0, self.soa.clone());
+        }
+        matching
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        name == self.origin || name.ends_with(&format!(".{}", self.origin))
+    }
+
+    /// Whether `name` is an empty non-terminal, existing only through names below it.
+    fn has_children(&self, name: &str) -> bool {
+        let suffix = format!(".{}", name);
+        self.records.keys().any(|k| k.ends_with(&suffix))
+    }
+
+    /// Answers a raw message, returning nothing for messages not worth a reply.
+    pub fn handle(&self, message: &[u8], limit: usize) -> Option<Vec<u8>> {
+        match wire::parse_query(message) {
+            Ok(query) => Some(self.answer(&query).encode(limit)),
+            Err(e) => {
+                verbose!("Ignoring malformed query: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// How long a TCP client may stay silent before its connection is closed.
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections beyond this many at a time are closed right away.
+const MAX_TCP_CONNECTIONS: usize = 64;
+
+/// Sockets bound for serving, listening on the same address for UDP and TCP.
+pub struct Server {
+    udp: UdpSocket,
+    tcp: TcpListener,
+}
+
+impl Server {
+    pub fn bind(addr: SocketAddr) -> Result<Server> {
+        let udp = UdpSocket::bind(addr)?;
+        let tcp = TcpListener::bind(udp.local_addr()?)?;
+        Ok(Server { udp, tcp })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    /// Serves queries until an error occurs on one of the sockets.
+    pub fn run(self, authority: Arc<Authority>) -> Result<()> {
+        let udp_authority = authority.clone();
+        let udp = self.udp;
+        let udp_thread = thread::spawn(move || serve_udp(&udp, &udp_authority));
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in self.tcp.incoming() {
+            let stream = stream?;
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                verbose!(
+                    "Refusing a TCP connection, {} are open",
+                    MAX_TCP_CONNECTIONS
+                );
+                continue;
+            }
+            let authority = authority.clone();
+            let connections = connections.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve_tcp(stream, &authority) {
+                    verbose!("TCP connection closed: {}", e);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        udp_thread
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("The UDP server panicked")))
+    }
+}
+
+/// Answers datagrams for good. Failing to receive or answer one, for example
+/// because its sender is unreachable, only drops that one.
+fn serve_udp(socket: &UdpSocket, authority: &Authority) -> Result<()> {
+    let mut buf = [0; 4096];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                verbose!("Unable to receive a UDP query: {}", e);
+                continue;
+            }
+        };
+        if let Some(reply) = authority.handle(&buf[..len], wire::UDP_LIMIT) {
+            if let Err(e) = socket.send_to(&reply, peer) {
+                verbose!("Unable to answer {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// Answers length prefixed messages until the client closes the connection
+/// or stays silent for `TCP_TIMEOUT`.
+fn serve_tcp(mut stream: TcpStream, authority: &Authority) -> Result<()> {
+    stream.set_read_timeout(Some(TCP_TIMEOUT))?;
+    stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+    loop {
+        let mut len = [0; 2];
+        match stream.read_exact(&mut len) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            other => other?,
+        }
+        let mut message = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message)?;
+        if let Some(reply) = authority.handle(&message, u16::MAX as usize) {
+            stream.write_all(&(reply.len() as u16).to_be_bytes())?;
+            stream.write_all(&reply)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wire::tests::{answers, query};
+    use super::wire::*;
+    use super::*;
+    use std::time::Duration;
+
+    fn authority() -> Authority {
+        let zone: Zone = serde_yaml::from_str(
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00::1
+      - name: nas
+        private_ip: 10.0.0.2
+        ttl: 300
+        alias: [files]
+",
+        )
+        .unwrap();
+        Authority::new(&zone)
+    }
+
+    fn ttls(authority: &Authority, name: &str, qtype: u16) -> Vec<u32> {
+        let query = parse_query(&query(1, name, qtype)).unwrap();
+        authority
+            .answer(&query)
+            .answers
+            .iter()
+            .map(|a| a.ttl)
+            .collect()
+    }
+
+    #[test]
+    fn answers_carry_the_ttl_of_their_record() {
+        let authority = authority();
+        assert_eq!(
+            ttls(&authority, "gw.office.int.example.com", TYPE_A),
+            vec![3600]
+        );
+        assert_eq!(
+            ttls(&authority, "files.office.int.example.com", TYPE_A),
+            vec![300, 300]
+        );
+    }
+
+    fn ask(authority: &Authority, name: &str, qtype: u16) -> (u8, Vec<(String, u16)>) {
+        let reply = authority.handle(&query(1, name, qtype), UDP_LIMIT).unwrap();
+        let (flags, answers) = answers(&reply);
+        ((flags & 0xf) as u8, answers)
+    }
+
+    #[test]
+    fn answers_addresses_and_follows_cnames() {
+        let authority = authority();
+        assert_eq!(
+            ask(&authority, "gw.office.int.example.com", TYPE_AAAA),
+            (
+                RCODE_NOERROR,
+                vec![("gw.office.int.example.com".to_string(), TYPE_AAAA)]
+            )
+        );
+        assert_eq!(
+            ask(&authority, "Files.Office.int.example.com.", TYPE_A),
+            (
+                RCODE_NOERROR,
+                vec![
+                    ("files.office.int.example.com".to_string(), TYPE_CNAME),
+                    ("nas.office.int.example.com".to_string(), TYPE_A),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn missing_names_and_types() {
+        let authority = authority();
+        assert_eq!(
+            ask(&authority, "nope.office.int.example.com", TYPE_A),
+            (RCODE_NXDOMAIN, vec![])
+        );
+        assert_eq!(
+            ask(&authority, "nas.office.int.example.com", TYPE_AAAA),
+            (RCODE_NOERROR, vec![])
+        );
+        assert_eq!(
+            ask(&authority, "www.example.com", TYPE_A),
+            (RCODE_REFUSED, vec![])
+        );
+        assert_eq!(
+            ask(&authority, "int.example.com", TYPE_SOA),
+            (
+                RCODE_NOERROR,
+                vec![("int.example.com".to_string(), TYPE_SOA)]
+            )
+        );
+    }
+
+    #[test]
+    fn serves_over_udp_and_tcp() {
+        let server = Server::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let authority = Arc::new(authority());
+        thread::spawn(move || server.run(authority));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&query(1, "gw.office.int.example.com", TYPE_A), addr)
+            .unwrap();
+        let mut buf = [0; 512];
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(answers(&buf[..len]).1.len(), 1);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let message = query(2, "office.int.example.com", TYPE_A);
+        stream
+            .write_all(&(message.len() as u16).to_be_bytes())
+            .unwrap();
+        stream.write_all(&message).unwrap();
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut reply = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(answers(&reply).1.len(), 2);
+    }
+}
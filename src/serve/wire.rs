@@ -0,0 +1,363 @@
+//! Just enough of the RFC 1035 wire format to answer authoritative queries.
+use anyhow::{anyhow, Result};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+pub const TYPE_CAA: u16 = 257;
+pub const CLASS_IN: u16 = 1;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_FORMERR: u8 = 1;
+pub const RCODE_NXDOMAIN: u8 = 3;
+pub const RCODE_NOTIMP: u8 = 4;
+pub const RCODE_REFUSED: u8 = 5;
+
+/// The largest response sent over UDP to clients that did not advertise EDNS.
+pub const UDP_LIMIT: usize = 512;
+
+const HEADER_LEN: usize = 12;
+const FLAG_QR: u16 = 0x8000;
+const FLAG_AA: u16 = 0x0400;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+#[derive(Debug)]
+pub struct Query {
+    pub id: u16,
+    pub flags: u16,
+    pub question: Option<Question>,
+}
+
+impl Query {
+    pub fn opcode(&self) -> u16 {
+        (self.flags >> 11) & 0xf
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rdata {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Txt(String),
+    Mx(u16, String),
+    Srv(u16, u16, u16, String),
+    Caa(u8, String, String),
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+}
+
+impl Rdata {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            Rdata::A(_) => TYPE_A,
+            Rdata::Aaaa(_) => TYPE_AAAA,
+            Rdata::Cname(_) => TYPE_CNAME,
+            Rdata::Ns(_) => TYPE_NS,
+            Rdata::Ptr(_) => TYPE_PTR,
+            Rdata::Txt(_) => TYPE_TXT,
+            Rdata::Mx(..) => TYPE_MX,
+            Rdata::Srv(..) => TYPE_SRV,
+            Rdata::Caa(..) => TYPE_CAA,
+            Rdata::Soa { .. } => TYPE_SOA,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub name: String,
+    pub ttl: u32,
+    pub rdata: Rdata,
+}
+
+#[derive(Debug, Default)]
+pub struct Response {
+    pub id: u16,
+    pub recursion_desired: bool,
+    pub authoritative: bool,
+    pub rcode: u8,
+    pub question: Option<Question>,
+    pub answers: Vec<Answer>,
+    pub authority: Vec<Answer>,
+}
+
+impl Response {
+    pub fn to(query: &Query) -> Response {
+        Response {
+            id: query.id,
+            recursion_desired: query.flags & FLAG_RD != 0,
+            rcode: RCODE_NOERROR,
+            question: query.question.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Encodes the response, truncating it to the question alone if it exceeds `limit`.
+    pub fn encode(&self, limit: usize) -> Vec<u8> {
+        let full = self.encode_sections(true);
+        if full.len() <= limit {
+            return full;
+        }
+        let mut truncated = self.encode_sections(false);
+        let flags = u16::from_be_bytes([truncated[2], truncated[3]]) | FLAG_TC;
+        truncated[2..4].copy_from_slice(&flags.to_be_bytes());
+        truncated
+    }
+
+    fn encode_sections(&self, records: bool) -> Vec<u8> {
+        let mut flags = FLAG_QR | u16::from(self.rcode);
+        if self.authoritative {
+            flags |= FLAG_AA;
+        }
+        if self.recursion_desired {
+            flags |= FLAG_RD;
+        }
+        let (answers, authority) = if records {
+            (&self.answers[..], &self.authority[..])
+        } else {
+            (&[][..], &[][..])
+        };
+        let mut out = Vec::with_capacity(UDP_LIMIT);
+        out.extend(self.id.to_be_bytes());
+        out.extend(flags.to_be_bytes());
+        out.extend((self.question.is_some() as u16).to_be_bytes());
+        out.extend((answers.len() as u16).to_be_bytes());
+        out.extend((authority.len() as u16).to_be_bytes());
+        out.extend(0u16.to_be_bytes());
+        if let Some(q) = &self.question {
+            encode_name(&mut out, &q.name);
+            out.extend(q.qtype.to_be_bytes());
+            out.extend(q.qclass.to_be_bytes());
+        }
+        for answer in answers.iter().chain(authority) {
+            encode_answer(&mut out, answer);
+        }
+        out
+    }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        out.push(label.len() as u8);
+        out.extend(label.as_bytes());
+    }
+    out.push(0);
+}
+
+fn encode_answer(out: &mut Vec<u8>, answer: &Answer) {
+    encode_name(out, &answer.name);
+    out.extend(answer.rdata.rtype().to_be_bytes());
+    out.extend(CLASS_IN.to_be_bytes());
+    out.extend(answer.ttl.to_be_bytes());
+    let mut rdata = Vec::new();
+    match &answer.rdata {
+        Rdata::A(ip) => rdata.extend(ip.octets()),
+        Rdata::Aaaa(ip) => rdata.extend(ip.octets()),
+        Rdata::Cname(name) | Rdata::Ns(name) | Rdata::Ptr(name) => encode_name(&mut rdata, name),
+        Rdata::Txt(text) => {
+            for chunk in text.as_bytes().chunks(255) {
+                rdata.push(chunk.len() as u8);
+                rdata.extend(chunk);
+            }
+        }
+        Rdata::Mx(preference, exchange) => {
+            rdata.extend(preference.to_be_bytes());
+            encode_name(&mut rdata, exchange);
+        }
+        Rdata::Srv(priority, weight, port, target) => {
+            for n in [priority, weight, port] {
+                rdata.extend(n.to_be_bytes());
+            }
+            encode_name(&mut rdata, target);
+        }
+        Rdata::Caa(flags, tag, value) => {
+            rdata.push(*flags);
+            rdata.push(tag.len() as u8);
+            rdata.extend(tag.as_bytes());
+            rdata.extend(value.as_bytes());
+        }
+        Rdata::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => {
+            encode_name(&mut rdata, mname);
+            encode_name(&mut rdata, rname);
+            for n in [serial, refresh, retry, expire, minimum] {
+                rdata.extend(n.to_be_bytes());
+            }
+        }
+    }
+    out.extend((rdata.len() as u16).to_be_bytes());
+    out.extend(rdata);
+}
+
+/// Parses the header and the first question of a query.
+pub fn parse_query(buf: &[u8]) -> Result<Query> {
+    if buf.len() < HEADER_LEN {
+        return Err(anyhow!("Message shorter than a DNS header"));
+    }
+    let id = u16::from_be_bytes([buf[0], buf[1]]);
+    let flags = u16::from_be_bytes([buf[2], buf[3]]);
+    if flags & FLAG_QR != 0 {
+        return Err(anyhow!("Message is a response"));
+    }
+    let qdcount = u16::from_be_bytes([buf[4], buf[5]]);
+    let mut query = Query {
+        id,
+        flags,
+        question: None,
+    };
+    if qdcount > 0 {
+        let (name, pos) = parse_name(buf, HEADER_LEN)?;
+        let fixed = buf.get(pos..pos + 4).ok_or(anyhow!("Truncated question"))?;
+        query.question = Some(Question {
+            name,
+            qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+            qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+        });
+    }
+    Ok(query)
+}
+
+/// Reads a possibly compressed name at `pos`, returning it and the position after it.
+fn parse_name(buf: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *buf.get(pos).ok_or(anyhow!("Truncated name"))? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Ok((name, end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let low = *buf.get(pos + 1).ok_or(anyhow!("Truncated pointer"))? as usize;
+                end.get_or_insert(pos + 2);
+                pos = ((l & 0x3f) << 8) | low;
+            }
+            l if l <= 63 => {
+                let label = buf
+                    .get(pos + 1..pos + 1 + l)
+                    .ok_or(anyhow!("Truncated label"))?;
+                labels.push(String::from_utf8_lossy(label).to_string());
+                pos += 1 + l;
+            }
+            _ => return Err(anyhow!("Invalid label length")),
+        }
+    }
+    Err(anyhow!("Too many labels or a compression loop"))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Encodes a query for `name`, as a stub resolver would.
+    pub fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(id.to_be_bytes());
+        out.extend(FLAG_RD.to_be_bytes());
+        out.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+        encode_name(&mut out, name);
+        out.extend(qtype.to_be_bytes());
+        out.extend(CLASS_IN.to_be_bytes());
+        out
+    }
+
+    /// Decodes the header and the owner names and types of the answer section.
+    pub fn answers(buf: &[u8]) -> (u16, Vec<(String, u16)>) {
+        let flags = u16::from_be_bytes([buf[2], buf[3]]);
+        let ancount = u16::from_be_bytes([buf[6], buf[7]]);
+        let (_, mut pos) = parse_name(buf, HEADER_LEN).unwrap();
+        pos += 4;
+        let mut answers = Vec::new();
+        for _ in 0..ancount {
+            let (name, p) = parse_name(buf, pos).unwrap();
+            let rtype = u16::from_be_bytes([buf[p], buf[p + 1]]);
+            let rdlen = u16::from_be_bytes([buf[p + 8], buf[p + 9]]) as usize;
+            answers.push((name, rtype));
+            pos = p + 10 + rdlen;
+        }
+        (flags, answers)
+    }
+
+    #[test]
+    fn parses_queries() {
+        let q = parse_query(&query(7, "gw.office.int.example.com", TYPE_AAAA)).unwrap();
+        assert_eq!(q.id, 7);
+        assert_eq!(
+            q.question,
+            Some(Question {
+                name: "gw.office.int.example.com".to_string(),
+                qtype: TYPE_AAAA,
+                qclass: CLASS_IN,
+            })
+        );
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        let mut buf = query(1, "example.com", TYPE_A);
+        let at = buf.len();
+        buf.extend([3, b'w', b'w', b'w', 0xc0, HEADER_LEN as u8]);
+        assert_eq!(
+            parse_name(&buf, at).unwrap(),
+            ("www.example.com".to_string(), at + 6)
+        );
+    }
+
+    #[test]
+    fn large_responses_are_truncated() {
+        let q = parse_query(&query(1, "a.example.com", TYPE_TXT)).unwrap();
+        let mut response = Response::to(&q);
+        response.answers = vec![
+            Answer {
+                name: "a.example.com".to_string(),
+                ttl: 60,
+                rdata: Rdata::Txt("x".repeat(600)),
+            };
+            1
+        ];
+        let full = response.encode(usize::MAX);
+        let truncated = response.encode(UDP_LIMIT);
+        assert!(full.len() > UDP_LIMIT);
+        let (flags, answers) = super::tests::answers(&truncated);
+        assert!(flags & FLAG_TC != 0);
+        assert!(answers.is_empty());
+    }
+}
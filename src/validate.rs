@@ -0,0 +1,698 @@
+use crate::model::{lists_zones, CustomRecord, Network, Server, Zone};
+use anyhow::Result;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+/// A problem found in a config file, with the line it was found on when known.
+/// Warnings point out likely mistakes without stopping netmgr.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+    pub warning: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if self.warning {
+            write!(f, " warning:")?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+/// The number of problems that are errors rather than warnings.
+pub fn errors(problems: &[Problem]) -> usize {
+    problems.iter().filter(|p| !p.warning).count()
+}
+
+/// Parses and checks the config at `path`. IO errors are returned as errors,
+/// everything wrong with the config itself as problems.
+pub fn validate_file(path: &Path) -> Result<Vec<Problem>> {
+    let source = fs::read_to_string(path)?;
+    let zones = match Zone::parse_all(&source) {
+        Ok(zones) => zones,
+        Err(e) => {
+            return Ok(vec![Problem {
+                file: path.to_path_buf(),
+                line: e.location().map(|l| l.line()),
+                message: e.to_string(),
+                warning: false,
+            }])
+        }
+    };
+    let listed = lists_zones(&source);
+    let mut problems = Vec::new();
+    if zones.is_empty() {
+        let mut v = Validator {
+            file: path,
+            source: &source,
+            base: Vec::new(),
+            problems: Vec::new(),
+            addresses: HashMap::new(),
+        };
+        v.report(&[("zones", None)], "the zones list is empty".to_string());
+        return Ok(v.problems);
+    }
+    let mut domains: HashMap<&String, usize> = HashMap::new();
+    for zone in &zones {
+        let mut v = Validator {
+            file: path,
+            source: &source,
+            base: Vec::new(),
+            problems: Vec::new(),
+            addresses: HashMap::new(),
+        };
+        if listed {
+            let seen = domains.entry(&zone.domain).or_default();
+            *seen += 1;
+            v.base.push(("zones", None));
+            v.base
+                .extend((0..*seen).map(|_| ("domain", Some(zone.domain.as_str()))));
+            if *seen > 1 {
+                v.report(&[], format!("duplicate zone {}", zone.domain));
+            }
+        }
+        v.check_zone(zone);
+        problems.extend(v.problems);
+    }
+    Ok(problems)
+}
+
+/// A `key` or `key: value` pair used to find the line a problem is on.
+type Anchor<'a> = (&'a str, Option<&'a str>);
+
+struct Validator<'a> {
+    file: &'a Path,
+    source: &'a str,
+    /// Where the zone starts in a file listing several.
+    base: Vec<Anchor<'a>>,
+    problems: Vec<Problem>,
+    /// The server and network each address was first seen on.
+    addresses: HashMap<IpAddr, (String, String)>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, anchors: &[Anchor], message: String) {
+        self.push(anchors, message, false);
+    }
+
+    fn warn(&mut self, anchors: &[Anchor], message: String) {
+        self.push(anchors, message, true);
+    }
+
+    fn push(&mut self, anchors: &[Anchor], message: String, warning: bool) {
+        let anchors: Vec<Anchor> = self.base.iter().chain(anchors).cloned().collect();
+        self.problems.push(Problem {
+            file: self.file.to_path_buf(),
+            line: locate(self.source, &anchors),
+            message,
+            warning,
+        });
+    }
+
+    fn check_zone(&mut self, zone: &Zone) {
+        self.check_name(&[("domain", None)], "domain", &zone.domain);
+        self.check_name(
+            &[("private_prefix", None)],
+            "private_prefix",
+            &zone.private_prefix,
+        );
+
+        let mut networks: HashMap<&String, usize> = HashMap::new();
+        for network in &zone.networks {
+            let seen = networks.entry(&network.name).or_default();
+            *seen += 1;
+            let mut at = vec![("networks", None)];
+            at.extend((0..*seen).map(|_| ("name", Some(network.name.as_str()))));
+            if *seen > 1 {
+                self.report(&at, format!("duplicate network {}", network.name));
+            }
+            if network.name == zone.private_prefix {
+                self.report(
+                    &at,
+                    format!(
+                        "network {} has the same name as the private_prefix",
+                        network.name
+                    ),
+                );
+            }
+            self.check_name(&at, "network name", &network.name);
+            self.check_ttl(&at, network.ttl, &network.name);
+            self.check_network(&at, network);
+            self.check_records(&at, &network.records);
+        }
+
+        self.check_records(&[], &zone.records);
+        self.check_ttl(&[], zone.ttl, &zone.domain);
+
+        // Catch-all for collisions the checks above do not describe more precisely.
+        let mut kinds: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+        for r in zone.all_records() {
+            kinds.entry(r.name()).or_default().insert(r.kind());
+        }
+        for (name, kinds) in kinds {
+            if kinds.contains("CNAME") && kinds.len() > 1 {
+                self.report(
+                    &[],
+                    format!("{} would be both a CNAME and another record type", name),
+                );
+            }
+            if name.len() > 253 {
+                self.report(&[], format!("{} is longer than 253 characters", name));
+            }
+        }
+    }
+
+    fn check_network(&mut self, at: &[Anchor], network: &Network) {
+        let mut root_at = at.to_vec();
+        root_at.push(("root", None));
+        self.check_name(&root_at, "root", &network.root);
+        if !network.servers.iter().any(|s| s.name == network.root) {
+            self.report(
+                &root_at,
+                format!(
+                    "root {} is not a server in network {}",
+                    network.root, network.name
+                ),
+            );
+        }
+
+        let mut subnets = Vec::new();
+        for subnet in &network.subnet {
+            match subnet.parse::<IpNet>() {
+                Ok(net) => subnets.push(net),
+                Err(_) => {
+                    let mut at = at.to_vec();
+                    at.push(("subnet", None));
+                    self.report(
+                        &at,
+                        format!("invalid subnet {} for network {}", subnet, network.name),
+                    );
+                }
+            }
+        }
+
+        let mut servers: HashMap<&String, usize> = HashMap::new();
+        let mut aliases = HashSet::new();
+        let server_names: HashSet<&String> = network.servers.iter().map(|s| &s.name).collect();
+        for server in &network.servers {
+            // Repeating the anchor finds the n:th server with a duplicated name.
+            let seen = servers.entry(&server.name).or_default();
+            *seen += 1;
+            let mut at = at.to_vec();
+            at.push(("servers", None));
+            at.extend((0..*seen).map(|_| ("name", Some(server.name.as_str()))));
+            if *seen > 1 {
+                self.report(
+                    &at,
+                    format!(
+                        "duplicate server {} in network {}",
+                        server.name, network.name
+                    ),
+                );
+            }
+            self.check_name(&at, "server name", &server.name);
+            self.check_ttl(&at, server.ttl, &server.name);
+            self.check_records(&at, &server.records);
+
+            if server.private_ip.is_empty() && !subnets.iter().any(|net| net.addr().is_ipv4()) {
+                self.report(
+                    &at,
+                    format!(
+                        "server {} has no private_ip and network {} has no IPv4 subnet to allocate one from",
+                        server.name, network.name
+                    ),
+                );
+            }
+            let mut ip_at = at.clone();
+            ip_at.push(("private_ip", None));
+            for ip in &server.private_ip {
+                match ip.parse::<Ipv4Addr>() {
+                    Ok(ip) => self.check_address(&ip_at, network, server, &subnets, ip.into()),
+                    Err(_) => self.report(
+                        &ip_at,
+                        format!("invalid IPv4 address {} for server {}", ip, server.name),
+                    ),
+                }
+            }
+            let mut ip_at = at.clone();
+            ip_at.push(("private_ipv6", None));
+            for ip in &server.private_ipv6 {
+                match ip.parse::<Ipv6Addr>() {
+                    Ok(ip) => self.check_address(&ip_at, network, server, &subnets, ip.into()),
+                    Err(_) => self.report(
+                        &ip_at,
+                        format!("invalid IPv6 address {} for server {}", ip, server.name),
+                    ),
+                }
+            }
+
+            let mut alias_at = at.clone();
+            alias_at.push(("alias", None));
+            for alias in &server.alias {
+                let alias_settings_at: Vec<Anchor> = alias_at
+                    .iter()
+                    .cloned()
+                    .chain([("name", Some(alias.name.as_str()))])
+                    .collect();
+                self.check_ttl(&alias_settings_at, alias.ttl, &alias.name);
+                let alias = &alias.name;
+                self.check_name(&alias_at, "alias", alias);
+                if server_names.contains(alias) {
+                    self.report(
+                        &alias_at,
+                        format!(
+                            "alias {} of server {} collides with the server {}",
+                            alias, server.name, alias
+                        ),
+                    );
+                } else if !aliases.insert(alias) {
+                    self.report(
+                        &alias_at,
+                        format!(
+                            "alias {} is used more than once in network {}",
+                            alias, network.name
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks that `ip` is in the subnets of its network, if it declares any of
+    /// the same family, and that no other server uses it.
+    fn check_address(
+        &mut self,
+        at: &[Anchor],
+        network: &Network,
+        server: &Server,
+        subnets: &[IpNet],
+        ip: IpAddr,
+    ) {
+        let family: Vec<&IpNet> = subnets
+            .iter()
+            .filter(|net| net.addr().is_ipv4() == ip.is_ipv4())
+            .collect();
+        match family.iter().find(|net| net.contains(&ip)) {
+            Some(net) => {
+                let host_bits = net.max_prefix_len() - net.prefix_len();
+                if host_bits >= 2 && ip == net.network() {
+                    self.warn(
+                        at,
+                        format!(
+                            "{} of server {} is the network address of {}",
+                            ip, server.name, net
+                        ),
+                    );
+                } else if host_bits >= 2 && ip.is_ipv4() && ip == net.broadcast() {
+                    self.warn(
+                        at,
+                        format!(
+                            "{} of server {} is the broadcast address of {}",
+                            ip, server.name, net
+                        ),
+                    );
+                }
+            }
+            None if !family.is_empty() => self.report(
+                at,
+                format!(
+                    "{} of server {} is outside the subnets of network {}",
+                    ip, server.name, network.name
+                ),
+            ),
+            None => {}
+        }
+
+        let owner = (server.name.clone(), network.name.clone());
+        match self.addresses.get(&ip) {
+            Some((other, other_network)) => {
+                let message = format!(
+                    "{} of server {} in network {} is also used by server {} in network {}",
+                    ip, server.name, network.name, other, other_network
+                );
+                self.report(at, message);
+            }
+            None => {
+                self.addresses.insert(ip, owner);
+            }
+        }
+    }
+
+    fn check_records(&mut self, at: &[Anchor], records: &[CustomRecord]) {
+        let mut at = at.to_vec();
+        at.push(("records", None));
+        for record in records {
+            let name = record.name();
+            // Service and policy records live under names like _ldap._tcp or _dmarc.
+            let invalid = name
+                .split('.')
+                .map(|label| check_label(label.strip_prefix('_').unwrap_or(label)))
+                .find_map(|checked| checked.err());
+            match invalid {
+                _ if name == "@" => {}
+                _ if name.ends_with('.') => self.report(
+                    &at,
+                    format!("record name {} ends with a dot and escapes the zone", name),
+                ),
+                Some(reason) => {
+                    self.report(&at, format!("record name {} is invalid: {}", name, reason))
+                }
+                None => {}
+            }
+            if let CustomRecord::Caa { tag, .. } = record {
+                if !["issue", "issuewild", "iodef"].contains(&tag.as_str()) {
+                    self.report(
+                        &at,
+                        format!("unknown CAA tag {}, use issue, issuewild or iodef", tag),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Cloudflare only accepts TTLs from 30 seconds (60 outside enterprise plans) to a day.
+    fn check_ttl(&mut self, at: &[Anchor], ttl: Option<u32>, owner: &str) {
+        if let Some(ttl) = ttl.filter(|ttl| !(30..=86400).contains(ttl)) {
+            let mut at = at.to_vec();
+            at.push(("ttl", None));
+            self.report(
+                &at,
+                format!(
+                    "ttl {} of {} is out of range, use 30 to 86400 seconds",
+                    ttl, owner
+                ),
+            );
+        }
+    }
+
+    /// Checks that `name` is made up of valid DNS labels and stays inside the zone.
+    fn check_name(&mut self, at: &[Anchor], what: &str, name: &str) {
+        if name.ends_with('.') {
+            self.report(
+                at,
+                format!("{} {} ends with a dot and escapes the zone", what, name),
+            );
+            return;
+        }
+        for label in name.split('.') {
+            if let Err(reason) = check_label(label) {
+                self.report(at, format!("{} {} is invalid: {}", what, name, reason));
+                return;
+            }
+        }
+    }
+}
+
+fn check_label(label: &str) -> std::result::Result<(), &'static str> {
+    if label.is_empty() {
+        return Err("empty label");
+    }
+    if label.len() > 63 {
+        return Err("labels can be at most 63 characters");
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+        return Err("labels cannot start or end with a hyphen");
+    }
+    if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err("labels may only contain letters, digits and hyphens");
+    }
+    Ok(())
+}
+
+/// Finds the line matching the last anchor, looking for each anchor after
+/// the line of the previous one. Lines are numbered from 1.
+fn locate(source: &str, anchors: &[Anchor]) -> Option<usize> {
+    let mut start = 0;
+    let mut found = None;
+    for (key, value) in anchors {
+        let (idx, _) = source
+            .lines()
+            .enumerate()
+            .skip(start)
+            .find(|(_, line)| matches_anchor(line, key, *value))?;
+        found = Some(idx + 1);
+        start = idx + 1;
+    }
+    found
+}
+
+fn matches_anchor(line: &str, key: &str, value: Option<&str>) -> bool {
+    let line = line.trim_start().trim_start_matches("- ").trim_start();
+    let rest = match line
+        .strip_prefix(key)
+        .and_then(|rest| rest.trim_start().strip_prefix(':'))
+    {
+        Some(rest) => rest.trim(),
+        None => return false,
+    };
+    match value {
+        Some(value) => rest.trim_matches(|c| c == '"' || c == '\'') == value,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn validate_str(test: &str, source: &str) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("netmgr-{}.yaml", test));
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+        let problems = validate_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        problems
+            .into_iter()
+            .map(|p| {
+                let severity = if p.warning { "warning: " } else { "" };
+                format!("{}: {}{}", p.line.unwrap_or(0), severity, p.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let problems = validate_str(
+            "valid",
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    subnet: [10.0.0.0/24, fd00::/64]
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        private_ipv6: fd00::1
+      - name: nas
+        private_ip: 10.0.0.2
+        alias: [files]
+",
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn problems_are_reported_with_their_line() {
+        let problems = validate_str(
+            "invalid",
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.300
+      - name: nas
+        private_ip: 10.0.0.2
+        alias: [gw, files]
+      - name: nas
+        private_ip: 10.0.0.3
+      - name: bad_name.
+        private_ip: 10.0.0.4
+",
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "8: invalid IPv4 address 10.0.0.300 for server gw",
+                "11: alias gw of server nas collides with the server gw",
+                "12: duplicate server nas in network office",
+                "14: server name bad_name. ends with a dot and escapes the zone",
+                "0: gw.office.int.example.com would be both a CNAME and another record type",
+            ]
+        );
+    }
+
+    #[test]
+    fn addresses_are_checked_against_their_subnet() {
+        let problems = validate_str(
+            "subnets",
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    subnet: [10.0.0.0/24, fd00::/64]
+    servers:
+      - name: gw
+        private_ip: 10.0.0.0
+        private_ipv6: fd00:0:0:1::1
+      - name: nas
+        private_ip: [10.0.0.255, 10.0.1.2]
+  - name: lab
+    root: lab1
+    servers:
+      - name: lab1
+        private_ip: 10.0.0.0
+",
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "9: warning: 10.0.0.0 of server gw is the network address of 10.0.0.0/24",
+                "10: fd00:0:0:1::1 of server gw is outside the subnets of network office",
+                "12: warning: 10.0.0.255 of server nas is the broadcast address of 10.0.0.0/24",
+                "12: 10.0.1.2 of server nas is outside the subnets of network office",
+                "17: 10.0.0.0 of server lab1 in network lab is also used by server gw in network office",
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_records_are_checked() {
+        let problems = validate_str(
+            "records",
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        records:
+          - type: SRV
+            name: _ldap._tcp
+            priority: 0
+            weight: 0
+            port: 389
+            target: gw.office.int.example.com
+          - type: TXT
+            name: bad_name
+            value: x
+          - type: CAA
+            tag: issues
+            value: letsencrypt.org
+",
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "9: record name bad_name is invalid: labels may only contain letters, digits and hyphens",
+                "9: unknown CAA tag issues, use issue, issuewild or iodef",
+            ]
+        );
+    }
+
+    #[test]
+    fn ttls_are_checked() {
+        let problems = validate_str(
+            "ttl",
+            "domain: example.com
+private_prefix: int
+ttl: 10
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        private_ip: 10.0.0.1
+        ttl: 300
+        alias:
+          - name: www
+            ttl: 100000
+",
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "13: ttl 100000 of www is out of range, use 30 to 86400 seconds",
+                "3: ttl 10 of example.com is out of range, use 30 to 86400 seconds",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_zone_of_a_list_is_checked() {
+        let problems = validate_str(
+            "zones",
+            "zones:
+  - domain: example.com
+    private_prefix: int
+    networks:
+      - name: office
+        root: gw
+        servers:
+          - name: gw
+            private_ip: 10.0.0.1
+  - domain: example.com
+    private_prefix: int
+    networks:
+      - name: office
+        root: gw
+        servers:
+          - name: gw
+            private_ip: 10.0.0.1
+",
+        );
+        assert_eq!(problems, vec!["10: duplicate zone example.com"]);
+    }
+
+    #[test]
+    fn empty_zone_lists_are_problems() {
+        let problems = validate_str("empty", "# No zones yet\nzones: []\n");
+        assert_eq!(problems, vec!["2: the zones list is empty"]);
+    }
+
+    #[test]
+    fn syntax_errors_are_problems() {
+        let problems = validate_str("syntax", "domain: example.com\nnetworks: []\n");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("private_prefix"));
+    }
+
+    #[test]
+    fn unknown_keys_are_problems() {
+        let problems = validate_str(
+            "unknown",
+            "domain: example.com
+private_prefix: int
+networks:
+  - name: office
+    root: gw
+    servers:
+      - name: gw
+        privte_ip: 10.0.0.1
+",
+        );
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("8: "), "{}", problems[0]);
+        assert!(problems[0].contains("unknown field `privte_ip`"));
+    }
+}